@@ -0,0 +1,325 @@
+// Account balances and nonces, as an alternative to unspent outputs
+
+use crate::crypto::sha256::sha256d;
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::smt::SparseMerkleTree;
+use crate::transaction::{Address, Transaction, TxOutput};
+use std::collections::{HashMap, VecDeque};
+
+/// The state of one account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Account {
+    pub balance: u64,
+    /// Number of transactions the account has sent; the next one must carry it as its nonce.
+    pub nonce: u64,
+}
+
+/// Every account that has ever been credited or has sent a transaction, and the
+/// block rewards not yet credited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountState {
+    accounts: HashMap<Address, Account>,
+    /// Coinbase outputs after genesis, oldest first; each is credited once
+    /// `coinbase_maturity` blocks have passed.
+    immature: VecDeque<Reward>,
+    /// Commits to `accounts` and `immature`, kept up to date as they change.
+    tree: SparseMerkleTree,
+    coinbase_maturity: u32,
+}
+
+/// A coinbase output waiting to mature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reward {
+    /// Height of the block that created the output.
+    height: u32,
+    /// Position of the output in the coinbase.
+    index: u32,
+    output: TxOutput,
+}
+
+/// The accounts a block touched and what they held before it, so that
+/// `AccountState::disconnect` can revert it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountUndo {
+    previous: Vec<(Address, Option<Account>)>,
+    matured: Vec<Reward>,
+    locked: usize,
+}
+
+impl AccountState {
+    /// Creates a state with no accounts whose block rewards are credited after
+    /// `coinbase_maturity` blocks.
+    pub fn new(coinbase_maturity: u32) -> Self {
+        AccountState {
+            accounts: HashMap::new(),
+            immature: VecDeque::new(),
+            tree: SparseMerkleTree::new(),
+            coinbase_maturity,
+        }
+    }
+
+    /// Returns the account at `address`; accounts never touched are empty.
+    pub fn get(&self, address: &Address) -> Account {
+        self.accounts.get(address).copied().unwrap_or_default()
+    }
+
+    /// The balance `address` may spend in a block at `height`, counting rewards
+    /// that mature by then.
+    pub fn balance(&self, address: &Address, height: u32) -> u64 {
+        self.immature
+            .iter()
+            .filter(|reward| reward.output.recipient == *address && self.matures_by(reward.height, height))
+            .fold(self.get(address).balance, |total, reward| total.saturating_add(reward.output.amount))
+    }
+
+    /// Number of accounts.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Returns `true` if there are no accounts.
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Commits to every account and every immature reward: the root of a sparse
+    /// Merkle tree keyed by the hash of each address, or of each reward's height
+    /// and output index, over the hashes of their encodings.
+    pub fn state_root(&self) -> Hash256 {
+        self.tree.root()
+    }
+
+    /// Applies the block at `height`: first credits the rewards that mature at
+    /// `height`, then applies its transactions in order.
+    ///
+    /// Every transaction but a coinbase must pass `check_transfer`; the outputs
+    /// are credited to their recipients and the fee leaves the sender. A coinbase only creates its outputs, which are
+    /// held back until they mature unless it is the genesis allocation; where it may
+    /// appear and how much it may create is checked by `Block::validate_coinbase`.
+    /// On error the state is left unchanged.
+    pub fn connect(&mut self, height: u32, transactions: &[Transaction]) -> Result<AccountUndo, ChainError> {
+        let mut undo = AccountUndo::default();
+        while let Some(&reward) = self.immature.front() {
+            if !self.matures_by(reward.height, height) {
+                break;
+            }
+            self.immature.pop_front();
+            self.tree.remove(&reward.key());
+            self.credit(&reward.output, &mut undo);
+            undo.matured.push(reward);
+        }
+        for transaction in transactions {
+            if let Err(err) = self.apply(height, transaction, &mut undo) {
+                self.disconnect(undo);
+                return Err(err);
+            }
+        }
+        Ok(undo)
+    }
+
+    /// Reverts a block applied by `connect`.
+    pub fn disconnect(&mut self, undo: AccountUndo) {
+        for _ in 0..undo.locked {
+            if let Some(reward) = self.immature.pop_back() {
+                self.tree.remove(&reward.key());
+            }
+        }
+        for (address, previous) in undo.previous.into_iter().rev() {
+            self.set(address, previous);
+        }
+        for reward in undo.matured.into_iter().rev() {
+            self.tree.insert(reward.key(), reward.value());
+            self.immature.push_front(reward);
+        }
+    }
+
+    fn matures_by(&self, created: u32, height: u32) -> bool {
+        created.saturating_add(self.coinbase_maturity) <= height
+    }
+
+    fn apply(&mut self, height: u32, transaction: &Transaction, undo: &mut AccountUndo) -> Result<(), ChainError> {
+        if transaction.is_coinbase() && height > 0 {
+            for (index, output) in transaction.outputs.iter().enumerate() {
+                let reward = Reward {
+                    height,
+                    index: index as u32,
+                    output: *output,
+                };
+                self.tree.insert(reward.key(), reward.value());
+                self.immature.push_back(reward);
+                undo.locked += 1;
+            }
+            return Ok(());
+        }
+        if !transaction.is_coinbase() {
+            let required = check_transfer(transaction, &self.get(&transaction.sender))?;
+            self.update(&transaction.sender, undo, |account| {
+                account.balance -= required;
+                account.nonce += 1;
+            });
+        }
+        for output in &transaction.outputs {
+            self.credit(output, undo);
+        }
+        Ok(())
+    }
+
+    fn credit(&mut self, output: &TxOutput, undo: &mut AccountUndo) {
+        // Credits never exceed the coins in existence, which fit in a u64.
+        self.update(&output.recipient, undo, |account| {
+            account.balance = account.balance.saturating_add(output.amount)
+        });
+    }
+
+    fn update(&mut self, address: &Address, undo: &mut AccountUndo, change: impl FnOnce(&mut Account)) {
+        let previous = self.accounts.get(address).copied();
+        undo.previous.push((*address, previous));
+        let mut account = previous.unwrap_or_default();
+        change(&mut account);
+        self.set(*address, Some(account));
+    }
+
+    /// Stores or, given `None`, deletes the account at `address`, keeping the tree in step.
+    fn set(&mut self, address: Address, account: Option<Account>) {
+        let key = Hash256(sha256d(&address.0));
+        match account {
+            Some(account) => {
+                let mut bytes = [0u8; 48];
+                bytes[0..32].copy_from_slice(&address.0);
+                bytes[32..40].copy_from_slice(&account.balance.to_le_bytes());
+                bytes[40..48].copy_from_slice(&account.nonce.to_le_bytes());
+                self.tree.insert(key, Hash256(sha256d(&bytes)));
+                self.accounts.insert(address, account);
+            }
+            None => {
+                self.tree.remove(&key);
+                self.accounts.remove(&address);
+            }
+        }
+    }
+}
+
+/// Checks that `transaction`, which must not be a coinbase, may be applied by a
+/// sender holding `sender`, and returns the amount it debits: the outputs plus
+/// the fee.
+///
+/// It must spend no outputs, carry the sender's next nonce and be covered by the
+/// sender's balance.
+pub fn check_transfer(transaction: &Transaction, sender: &Account) -> Result<u64, ChainError> {
+    let tx_id = transaction.id();
+    if !transaction.inputs.is_empty() {
+        return Err(ChainError::UnexpectedInputs(tx_id));
+    }
+    if transaction.nonce != sender.nonce {
+        return Err(ChainError::BadNonce {
+            tx_id,
+            expected: sender.nonce,
+            actual: transaction.nonce,
+        });
+    }
+    let required = transaction
+        .outputs
+        .iter()
+        .fold(transaction.fee as u128, |total, output| total + output.amount as u128);
+    if required > sender.balance as u128 {
+        return Err(ChainError::InsufficientBalance {
+            tx_id,
+            balance: sender.balance,
+            required,
+        });
+    }
+    Ok(required as u64)
+}
+
+impl Reward {
+    /// Where the reward sits in the state tree: the hash of its height and output index.
+    fn key(&self) -> Hash256 {
+        let mut bytes = [0u8; 8];
+        bytes[0..4].copy_from_slice(&self.height.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.index.to_le_bytes());
+        Hash256(sha256d(&bytes))
+    }
+
+    /// The hash of the reward's encoding.
+    fn value(&self) -> Hash256 {
+        let mut bytes = [0u8; 48];
+        bytes[0..4].copy_from_slice(&self.height.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.index.to_le_bytes());
+        bytes[8..40].copy_from_slice(&self.output.recipient.0);
+        bytes[40..48].copy_from_slice(&self.output.amount.to_le_bytes());
+        Hash256(sha256d(&bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use std::slice;
+
+    fn pay(key: &SigningKey, amount: u64) -> TxOutput {
+        TxOutput {
+            recipient: Address::of(key),
+            amount,
+        }
+    }
+
+    #[test]
+    fn each_transaction_takes_the_senders_next_nonce() {
+        let (alice, bob) = (SigningKey::from_seed(&[1; 32]), SigningKey::from_seed(&[2; 32]));
+        let mut accounts = AccountState::new(100);
+        accounts.connect(0, &[Transaction::coinbase(0, vec![pay(&alice, 100)])]).unwrap();
+        let first = Transaction::new_signed(&alice, vec![], vec![pay(&bob, 10)], 1, 0);
+        let second = Transaction::new_signed(&alice, vec![], vec![pay(&bob, 10)], 1, 1);
+        let before = accounts.clone();
+        // Nonces cannot be skipped, and a block failing part way changes nothing.
+        assert_eq!(
+            accounts.connect(1, slice::from_ref(&second)),
+            Err(ChainError::BadNonce {
+                tx_id: second.id(),
+                expected: 0,
+                actual: 1,
+            })
+        );
+        assert_eq!(accounts, before);
+        let undo = accounts.connect(1, &[first.clone(), second]).unwrap();
+        assert_eq!(accounts.get(&Address::of(&alice)), Account { balance: 78, nonce: 2 });
+        assert_eq!(accounts.get(&Address::of(&bob)), Account { balance: 20, nonce: 0 });
+        // Nor replayed.
+        assert_eq!(
+            accounts.connect(2, slice::from_ref(&first)),
+            Err(ChainError::BadNonce {
+                tx_id: first.id(),
+                expected: 2,
+                actual: 0,
+            })
+        );
+        accounts.disconnect(undo);
+        assert_eq!(accounts, before);
+        assert_eq!(accounts.state_root(), before.state_root());
+    }
+
+    #[test]
+    fn block_rewards_are_credited_once_mature() {
+        let miner = SigningKey::from_seed(&[1; 32]);
+        let address = Address::of(&miner);
+        let mut accounts = AccountState::new(2);
+        accounts.connect(0, &[Transaction::coinbase(0, vec![pay(&miner, 10)])]).unwrap();
+        accounts.connect(1, &[Transaction::coinbase(1, vec![pay(&miner, 50)])]).unwrap();
+        assert_eq!(accounts.get(&address).balance, 10);
+        assert_eq!((accounts.balance(&address, 2), accounts.balance(&address, 3)), (10, 60));
+
+        let spend = Transaction::new_signed(&miner, vec![], vec![pay(&miner, 58)], 1, 0);
+        assert!(matches!(
+            accounts.connect(2, slice::from_ref(&spend)),
+            Err(ChainError::InsufficientBalance { balance: 10, .. })
+        ));
+        let before = accounts.clone();
+        let undo = accounts.connect(3, slice::from_ref(&spend)).unwrap();
+        assert_eq!(accounts.get(&address), Account { balance: 59, nonce: 1 });
+        accounts.disconnect(undo);
+        assert_eq!(accounts, before);
+        assert_eq!(accounts.state_root(), before.state_root());
+    }
+}
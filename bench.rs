@@ -0,0 +1,314 @@
+// Benchmark suite for the demo binary's `bench` command: hashing throughput,
+// mining at several difficulties and full-chain validation
+
+use crate::block::{self, BlockHeader, BLOCK_VERSION, HEADER_LEN};
+use crate::blockchain::Blockchain;
+use crate::crypto::blake3::ChunkHasher;
+use crate::crypto::ed25519::SigningKey;
+use crate::crypto::sha256::{sha256, Sha256};
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::miner::{self, MinerConfig, MiningProgress};
+use crate::params::ChainParams;
+use crate::pow::{PowAlgorithm, PowKind};
+use crate::state::StateModel;
+use crate::target::Target;
+use crate::transaction::{Address, Transaction, TxOutput};
+use std::fmt;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// The revision the binary was built from, taken from `ANPOW_REVISION` at
+/// compile time, so reports from different builds can be told apart.
+pub const REVISION: &str = match option_env!("ANPOW_REVISION") {
+    Some(revision) => revision,
+    None => "unknown",
+};
+
+/// A target no hash realistically meets, so a search runs until its deadline.
+const UNREACHABLE_BITS: u32 = 0x0300_0001;
+
+/// About one hash in two meets this target, so chains to validate are cheap to build.
+const TRIVIAL_BITS: u32 = 0x207f_ffff;
+
+/// What the suite runs for one algorithm. Everything but the timings is
+/// deterministic, so two reports from the same configuration can be compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchConfig {
+    /// How long each hashing throughput measurement runs.
+    pub hash_duration: Duration,
+    /// Compact targets to mine at; see `Target::from_compact`.
+    pub mining_bits: Vec<u32>,
+    /// Headers mined at each target.
+    pub mining_blocks: u32,
+    /// Blocks in the chain `Blockchain::validate` is timed on, each carrying one payment.
+    pub validation_blocks: u32,
+}
+
+impl BenchConfig {
+    /// The default suite for `kind`. Scrypt hashes about a thousand times slower
+    /// than the others, so it skips the hardest target.
+    pub fn for_algorithm(kind: PowKind) -> Self {
+        let mining_bits = match kind {
+            PowKind::Scrypt { .. } => vec![0x2000_ffff, 0x1f0f_ffff],
+            PowKind::Sha256d | PowKind::Blake3 => vec![0x2000_ffff, 0x1f0f_ffff, 0x1f00_ffff],
+        };
+        BenchConfig {
+            hash_duration: Duration::from_secs(2),
+            mining_bits,
+            mining_blocks: 4,
+            validation_blocks: 200,
+        }
+    }
+}
+
+/// One result of the suite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    /// Which benchmark: `hash_header`, `hash_midstate_copy`, `hash_midstate`, `mine` or `validate`.
+    pub benchmark: &'static str,
+    pub algorithm: PowKind,
+    /// The compact target mined at, for `mine`.
+    pub bits: Option<u32>,
+    /// Work done: hashes tried, or blocks validated for `validate`.
+    pub count: u64,
+    /// `hashes` or `blocks`.
+    pub unit: &'static str,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// `count` per second.
+    pub fn rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.count as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Formats the measurement as a single-line JSON object, tagged with `REVISION`.
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"benchmark\":\"{}\",\"algorithm\":\"{}\",", self.benchmark, self.algorithm)?;
+        match self.bits {
+            Some(bits) => write!(f, "\"bits\":\"{:08x}\",", bits)?,
+            None => write!(f, "\"bits\":null,")?,
+        }
+        write!(
+            f,
+            "\"count\":{},\"unit\":\"{}\",\"seconds\":{:.6},\"rate\":{:.3},\"revision\":\"{}\"}}",
+            self.count,
+            self.unit,
+            self.elapsed.as_secs_f64(),
+            self.rate(),
+            REVISION
+        )
+    }
+}
+
+/// Runs every benchmark in `config` with `pow` on one thread, in order: hashing
+/// throughput, mining at each target, then validation.
+pub fn run<P: PowAlgorithm>(pow: &P, config: &BenchConfig) -> Result<Vec<Measurement>, ChainError> {
+    let mut measurements = hash_throughput(pow, config.hash_duration)?;
+    for &bits in &config.mining_bits {
+        measurements.push(mining(pow, bits, config.mining_blocks)?);
+    }
+    measurements.push(validation(pow.clone(), config.validation_blocks)?);
+    Ok(measurements)
+}
+
+/// A header that only depends on `seed` and `bits`, so mining it always takes the same hashes.
+fn sample_header(seed: u32, bits: u32) -> BlockHeader {
+    BlockHeader {
+        version: BLOCK_VERSION,
+        height: seed + 1,
+        previous_hash: Hash256([seed as u8; 32]),
+        merkle_root: Hash256([0x22; 32]),
+        state_root: Hash256([0x33; 32]),
+        timestamp: 1_700_000_000 + seed as u64,
+        bits,
+        nonce: 0,
+    }
+}
+
+/// Hashes per second three ways, each for `duration`: hashing and checking whole
+/// headers, as mining did before midstates, the midstate loop as it was before it
+/// wrote nonces in place, and the miner's own loop.
+fn hash_throughput<P: PowAlgorithm>(pow: &P, duration: Duration) -> Result<Vec<Measurement>, ChainError> {
+    let mut header = sample_header(0, UNREACHABLE_BITS);
+    let started = Instant::now();
+    let mut hashes = 0u64;
+    while started.elapsed() < duration {
+        for _ in 0..64 {
+            header.nonce = header.nonce.wrapping_add(1);
+            let hash = header.calculate_hash(pow);
+            if block::is_valid_hash(&hash, header.bits) {
+                return Err(ChainError::InvalidParams("benchmark header met its target"));
+            }
+        }
+        hashes += 64;
+    }
+    let whole_header = Measurement {
+        benchmark: "hash_header",
+        algorithm: pow.kind(),
+        bits: None,
+        count: hashes,
+        unit: "hashes",
+        elapsed: started.elapsed(),
+    };
+
+    let copying = copying_midstate(pow, duration)?;
+
+    let deadline = Some(Instant::now() + duration);
+    let progress = match mine_with_progress(pow, sample_header(0, UNREACHABLE_BITS), deadline) {
+        (Err(ChainError::MiningDeadlineExceeded { .. }), progress) => progress,
+        (Err(err), _) => return Err(err),
+        (Ok(_), _) => return Err(ChainError::InvalidParams("benchmark header met its target")),
+    };
+    let midstate = Measurement {
+        benchmark: "hash_midstate",
+        algorithm: pow.kind(),
+        bits: None,
+        count: progress.hashes,
+        unit: "hashes",
+        elapsed: progress.elapsed,
+    };
+    Ok(vec![whole_header, copying, midstate])
+}
+
+/// The baseline for `hash_midstate`: mining from a midstate the way it was done
+/// before nonces were written into it in place. Each try copies the 120-byte
+/// header, writes the nonce into the copy, clones the hasher that absorbed the
+/// first block and feeds it the rest, then compares the digest bytes. Scrypt has
+/// nothing to absorb ahead, so only the header copy differs for it.
+fn copying_midstate<P: PowAlgorithm>(pow: &P, duration: Duration) -> Result<Measurement, ChainError> {
+    let header = sample_header(0, UNREACHABLE_BITS).encode();
+    let with_nonce = |nonce: u32| {
+        let mut copy = header;
+        copy[HEADER_LEN - 4..].copy_from_slice(&nonce.to_le_bytes());
+        copy
+    };
+    let (hashes, elapsed) = match pow.kind() {
+        PowKind::Sha256d => {
+            let mut hasher = Sha256::new();
+            hasher.update(&header[..64]);
+            count_hashes(duration, |nonce| {
+                let mut hasher = hasher.clone();
+                hasher.update(&with_nonce(nonce)[64..]);
+                Hash256(sha256(&hasher.finalize()))
+            })?
+        }
+        PowKind::Blake3 => {
+            let mut hasher = ChunkHasher::new();
+            hasher.update(&header[..65]);
+            count_hashes(duration, |nonce| {
+                let mut hasher = hasher;
+                hasher.update(&with_nonce(nonce)[65..]);
+                Hash256(hasher.finalize())
+            })?
+        }
+        PowKind::Scrypt { .. } => count_hashes(duration, |nonce| pow.hash(&with_nonce(nonce)))?,
+    };
+    Ok(Measurement {
+        benchmark: "hash_midstate_copy",
+        algorithm: pow.kind(),
+        bits: None,
+        count: hashes,
+        unit: "hashes",
+        elapsed,
+    })
+}
+
+/// Hashes nonces from 1 up with `hash` for `duration`, comparing each digest with
+/// an unreachable target as the miner does, and returns how many it hashed and
+/// how long that took.
+fn count_hashes(duration: Duration, mut hash: impl FnMut(u32) -> Hash256) -> Result<(u64, Duration), ChainError> {
+    let target = Target::from_compact(UNREACHABLE_BITS)
+        .expect("a valid compact target")
+        .value()
+        .to_be_bytes();
+    let started = Instant::now();
+    let mut hashes = 0u64;
+    while started.elapsed() < duration {
+        for _ in 0..64 {
+            hashes += 1;
+            if hash(hashes as u32).0 <= target {
+                return Err(ChainError::InvalidParams("benchmark header met its target"));
+            }
+        }
+    }
+    Ok((hashes, started.elapsed()))
+}
+
+/// Mines `blocks` sample headers at `bits`.
+fn mining<P: PowAlgorithm>(pow: &P, bits: u32, blocks: u32) -> Result<Measurement, ChainError> {
+    let mut measurement = Measurement {
+        benchmark: "mine",
+        algorithm: pow.kind(),
+        bits: Some(bits),
+        count: 0,
+        unit: "hashes",
+        elapsed: Duration::ZERO,
+    };
+    for seed in 0..blocks {
+        let (result, progress) = mine_with_progress(pow, sample_header(seed, bits), None);
+        result?;
+        measurement.count += progress.hashes;
+        measurement.elapsed += progress.elapsed;
+    }
+    Ok(measurement)
+}
+
+/// Mines `header` on one thread and returns the outcome with the final progress report.
+fn mine_with_progress<P: PowAlgorithm>(
+    pow: &P,
+    header: BlockHeader,
+    deadline: Option<Instant>,
+) -> (Result<(BlockHeader, Hash256), ChainError>, MiningProgress) {
+    let (progress, reports) = mpsc::channel();
+    let config = MinerConfig {
+        deadline,
+        progress: Some(progress),
+        progress_interval: Duration::from_secs(3600),
+        ..MinerConfig::default()
+    };
+    let result = miner::mine(pow, header, &config);
+    let progress = reports.try_iter().last().expect("the miner reports when it ends");
+    (result, progress)
+}
+
+/// Builds an account chain of `blocks` blocks, each paying one coin, and times
+/// validating it from genesis.
+fn validation<P: PowAlgorithm>(pow: P, blocks: u32) -> Result<Measurement, ChainError> {
+    let payer = SigningKey::from_seed(&[1; 32]);
+    let payee = Address::of(&SigningKey::from_seed(&[2; 32]));
+    let mut params = ChainParams::new(pow.kind(), TRIVIAL_BITS);
+    params.state_model = StateModel::Account;
+    params.genesis_outputs = vec![TxOutput {
+        recipient: Address::of(&payer),
+        amount: 2 * blocks as u64,
+    }];
+    let mut blockchain = Blockchain::with_params(pow, params)?;
+    for nonce in 0..blocks as u64 {
+        let payment = TxOutput {
+            recipient: payee,
+            amount: 1,
+        };
+        let transaction = Transaction::new_signed(&payer, vec![], vec![payment], 1, nonce);
+        blockchain.add_block(payee, vec![transaction])?;
+    }
+
+    let started = Instant::now();
+    blockchain.validate()?;
+    Ok(Measurement {
+        benchmark: "validate",
+        algorithm: blockchain.pow.kind(),
+        bits: None,
+        count: blocks as u64,
+        unit: "blocks",
+        elapsed: started.elapsed(),
+    })
+}
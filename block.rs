@@ -0,0 +1,418 @@
+// Block structure and per-block validation
+
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::merkle::merkle_root;
+use crate::params::ChainParams;
+use crate::pow::PowAlgorithm;
+use crate::state::ChainState;
+use crate::target::Target;
+use crate::transaction::Transaction;
+use crate::versionbits;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of the encoded header that proof of work is computed over.
+pub const HEADER_LEN: usize = 120;
+
+/// Version of the genesis block and of blocks that signal no deployment; see
+/// `DeploymentStates::next_version`.
+pub const BLOCK_VERSION: u32 = versionbits::TOP_BITS;
+
+/// Number of timestamps `median_time_past` takes the median of.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// The header fields of a block: everything proof of work is computed over.
+///
+/// Each header links to its parent's hash and commits to its block's body
+/// through the Merkle root, so headers can be synced and checked without the
+/// bodies; see `HeaderChain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub height: u32,
+    pub previous_hash: Hash256,
+    /// Commits the header to the block's transactions; see `merkle::merkle_root`.
+    pub merkle_root: Hash256,
+    /// Commits the header to the ledger state after the block; see `ChainState::state_root`.
+    pub state_root: Hash256,
+    pub timestamp: u64,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+/// A header and the transactions its Merkle root commits to, coinbase first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockHeader {
+    /// Computes the proof-of-work hash of the header.
+    pub fn calculate_hash<P: PowAlgorithm>(&self, pow: &P) -> Hash256 {
+        pow.hash(&self.encode())
+    }
+
+    /// Serializes the header into a canonical, fixed-size byte string.
+    ///
+    /// Integers are little-endian and hashes are their raw 32 bytes. The body is
+    /// only committed to through the Merkle root, so hashing cost does not grow
+    /// with the number of transactions.
+    ///
+    /// The nonce comes last, so miners can hash everything before it once per
+    /// header; see `PowAlgorithm::midstate`.
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.height.to_le_bytes());
+        bytes[8..40].copy_from_slice(self.previous_hash.as_bytes());
+        bytes[40..72].copy_from_slice(self.merkle_root.as_bytes());
+        bytes[72..104].copy_from_slice(self.state_root.as_bytes());
+        bytes[104..112].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[112..116].copy_from_slice(&self.bits.to_le_bytes());
+        bytes[116..120].copy_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    /// Parses a header serialized by `encode`. Every byte string of the right
+    /// length is some header; whether it is a valid one is up to `validate_against`.
+    pub fn decode(bytes: &[u8; HEADER_LEN]) -> Self {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let hash_at = |at: usize| Hash256(bytes[at..at + 32].try_into().unwrap());
+        BlockHeader {
+            version: u32_at(0),
+            height: u32_at(4),
+            previous_hash: hash_at(8),
+            merkle_root: hash_at(40),
+            state_root: hash_at(72),
+            timestamp: u64::from_le_bytes(bytes[104..112].try_into().unwrap()),
+            bits: u32_at(112),
+            nonce: u32_at(116),
+        }
+    }
+
+    /// Checks that the header correctly extends `parent`, whose hash is
+    /// `parent_hash`: the next height, linkage and a hash meeting its claimed
+    /// target. Returns the header's hash.
+    ///
+    /// Whether the claimed target is the one retargeting expects and whether the
+    /// timestamp is in range depend on the headers before it and are checked by
+    /// `retarget::check_bits` and `check_timestamp`.
+    pub fn validate_against<P: PowAlgorithm>(
+        &self,
+        parent: &BlockHeader,
+        parent_hash: &Hash256,
+        pow: &P,
+    ) -> Result<Hash256, ChainError> {
+        let expected_height = parent.height + 1;
+        if self.height != expected_height {
+            return Err(ChainError::NonSequentialHeight {
+                expected: expected_height,
+                actual: self.height,
+            });
+        }
+        if self.previous_hash != *parent_hash {
+            return Err(ChainError::BadPrevHash {
+                height: self.height,
+                expected: *parent_hash,
+                actual: self.previous_hash,
+            });
+        }
+        let target = Target::from_compact(self.bits).ok_or(ChainError::BadBits {
+            height: self.height,
+            bits: self.bits,
+        })?;
+        let hash = self.calculate_hash(pow);
+        if !target.is_met_by(&hash) {
+            return Err(ChainError::BadPow { height: self.height });
+        }
+        Ok(hash)
+    }
+}
+
+impl AsRef<BlockHeader> for BlockHeader {
+    fn as_ref(&self) -> &BlockHeader {
+        self
+    }
+}
+
+impl AsRef<BlockHeader> for Block {
+    fn as_ref(&self) -> &BlockHeader {
+        &self.header
+    }
+}
+
+impl Block {
+    /// Builds the genesis block for a chain with the given parameters: it starts at
+    /// the target `params.bits` and carries the genesis allocations, if any, in the
+    /// chain's state model.
+    ///
+    /// Genesis is not mined; its hash only has to be reproducible.
+    pub fn genesis(params: &ChainParams) -> Self {
+        let transactions = if params.genesis_outputs.is_empty() {
+            vec![]
+        } else {
+            vec![Transaction::coinbase(0, params.genesis_outputs.clone())]
+        };
+        let mut state = ChainState::new(params);
+        state
+            .connect(0, &transactions)
+            .expect("a coinbase only creates outputs");
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            height: 0,
+            previous_hash: Hash256::ZERO,
+            merkle_root: compute_merkle_root(&transactions),
+            state_root: state.state_root(),
+            timestamp: 0,
+            bits: params.bits,
+            nonce: 0,
+        };
+        Block { header, transactions }
+    }
+
+    /// Height of the block in its chain; genesis is 0.
+    pub fn height(&self) -> u32 {
+        self.header.height
+    }
+
+    /// Size in bytes of the encoded header plus every encoded transaction.
+    pub fn size(&self) -> usize {
+        HEADER_LEN + self.transactions.iter().map(Transaction::size).sum::<usize>()
+    }
+
+    /// Computes the proof-of-work hash of the block's header.
+    pub fn calculate_hash<P: PowAlgorithm>(&self, pow: &P) -> Hash256 {
+        self.header.calculate_hash(pow)
+    }
+
+    /// Checks that the block correctly extends `parent`, whose hash is `parent_hash`:
+    /// the header checks of `BlockHeader::validate_against`, then a Merkle root
+    /// matching its transactions, no coinbase but the first transaction and a
+    /// valid signature on every other. Returns the block's hash.
+    ///
+    /// Whether the claimed target is the one retargeting expects, whether the
+    /// coinbase claims no more than it may and whether the state root matches
+    /// depend on the whole chain and are checked by `Blockchain::validate`.
+    pub fn validate_against<P: PowAlgorithm>(
+        &self,
+        parent: &BlockHeader,
+        parent_hash: &Hash256,
+        pow: &P,
+    ) -> Result<Hash256, ChainError> {
+        let hash = self.header.validate_against(parent, parent_hash, pow)?;
+        if self.header.merkle_root != compute_merkle_root(&self.transactions) {
+            return Err(ChainError::BadMerkleRoot { height: self.height() });
+        }
+        for transaction in self.transactions.iter().skip(1) {
+            if transaction.is_coinbase() {
+                return Err(ChainError::UnexpectedCoinbase(transaction.id()));
+            }
+            if !transaction.verify_signature() {
+                return Err(ChainError::InvalidSignature(transaction.id()));
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Checks that the block starts with its coinbase and that the coinbase claims
+    /// at most the block's subsidy plus the fees of its other transactions.
+    ///
+    /// Returns the number of new coins the coinbase issues beyond those fees.
+    pub fn validate_coinbase(&self, params: &ChainParams) -> Result<u64, ChainError> {
+        let (coinbase, rest) = self
+            .transactions
+            .split_first()
+            .ok_or(ChainError::BadCoinbase { height: self.height(), reason: "block has no transactions" })?;
+        if !coinbase.is_coinbase() {
+            return Err(ChainError::BadCoinbase { height: self.height(), reason: "first transaction is not a coinbase" });
+        }
+        // The upper 32 bits of the nonce are the extra nonce; the lower ones must be the height.
+        if coinbase.nonce & u32::MAX as u64 != self.height() as u64 || coinbase.fee != 0 {
+            return Err(ChainError::BadCoinbase { height: self.height(), reason: "coinbase nonce or fee is wrong" });
+        }
+        let fees = rest.iter().map(|transaction| transaction.fee as u128).sum::<u128>();
+        let claimed = coinbase.outputs.iter().map(|output| output.amount as u128).sum::<u128>();
+        let allowed = params.subsidy(self.height()) as u128 + fees;
+        if claimed > allowed {
+            return Err(ChainError::ExcessiveCoinbase {
+                height: self.height(),
+                claimed,
+                allowed,
+            });
+        }
+        Ok(claimed.saturating_sub(fees) as u64)
+    }
+}
+
+/// Checks that `header`'s timestamp is later than the median time past of `chain`,
+/// every block or header up to and including its parent, and no more than
+/// `params.max_future_drift` seconds ahead of `now`.
+///
+/// Unlike the parent's timestamp, the median cannot be dragged far ahead by one
+/// miner, and the drift bound keeps a block from pushing it, and the targets
+/// retargeting derives from it, arbitrarily far into the future.
+pub fn check_timestamp<H: AsRef<BlockHeader>>(
+    params: &ChainParams,
+    chain: &[H],
+    header: &BlockHeader,
+    now: u64,
+) -> Result<(), ChainError> {
+    let median_time_past = median_time_past(chain);
+    if header.timestamp <= median_time_past {
+        return Err(ChainError::TimestampTooOld {
+            height: header.height,
+            timestamp: header.timestamp,
+            median_time_past,
+        });
+    }
+    let max = now.saturating_add(params.max_future_drift);
+    if header.timestamp > max {
+        return Err(ChainError::TimestampTooFarAhead {
+            height: header.height,
+            timestamp: header.timestamp,
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// The median timestamp of the last 11 blocks of `chain`, or of all of them if
+/// there are fewer. Unlike a single timestamp, miners cannot move it much.
+pub fn median_time_past<H: AsRef<BlockHeader>>(chain: &[H]) -> u64 {
+    let mut times: Vec<u64> = chain
+        .iter()
+        .rev()
+        .take(MEDIAN_TIME_SPAN)
+        .map(|header| header.as_ref().timestamp)
+        .collect();
+    times.sort_unstable();
+    times.get(times.len() / 2).copied().unwrap_or(0)
+}
+
+/// Returns the current timestamp in seconds since UNIX_EPOCH.
+pub fn current_timestamp() -> Result<u64, ChainError> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .map_err(|_| ChainError::ClockError)
+}
+
+/// Computes the Merkle root committing to `transactions` and their signatures:
+/// the leaves are witness ids, not ids.
+pub fn compute_merkle_root(transactions: &[Transaction]) -> Hash256 {
+    let ids: Vec<Hash256> = transactions.iter().map(Transaction::witness_id).collect();
+    merkle_root(&ids)
+}
+
+/// Validates that the hash, read as a 256-bit integer, does not exceed the target encoded by `bits`.
+pub fn is_valid_hash(hash: &Hash256, bits: u32) -> bool {
+    Target::from_compact(bits).is_some_and(|target| target.is_met_by(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::pow::PowKind;
+    use crate::transaction::{Address, TxOutput};
+
+    #[test]
+    fn timestamps_stay_between_the_median_time_past_and_the_drift_bound() {
+        let params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        let genesis = Block::genesis(&params).header;
+        let chain: Vec<BlockHeader> = [0, 10, 20, 90, 30, 40]
+            .iter()
+            .enumerate()
+            .map(|(height, &timestamp)| BlockHeader {
+                height: height as u32,
+                timestamp,
+                ..genesis
+            })
+            .collect();
+        let next = |timestamp| BlockHeader {
+            height: 6,
+            timestamp,
+            ..genesis
+        };
+        // The median of 0, 10, 20, 30, 40 and 90 is 30, so a block may be stamped before its parent.
+        check_timestamp(&params, &chain, &next(31), 1_000).unwrap();
+        for timestamp in [30, 29] {
+            assert_eq!(
+                check_timestamp(&params, &chain, &next(timestamp), 1_000),
+                Err(ChainError::TimestampTooOld {
+                    height: 6,
+                    timestamp,
+                    median_time_past: 30,
+                })
+            );
+        }
+        check_timestamp(&params, &chain, &next(1_000 + 7_200), 1_000).unwrap();
+        assert_eq!(
+            check_timestamp(&params, &chain, &next(1_000 + 7_201), 1_000),
+            Err(ChainError::TimestampTooFarAhead {
+                height: 6,
+                timestamp: 8_201,
+                max: 8_200,
+            })
+        );
+    }
+
+    #[test]
+    fn headers_round_trip_through_their_encoding() {
+        let header = BlockHeader {
+            version: 0x2000_0006,
+            height: 0x0102_0304,
+            previous_hash: Hash256([0x11; 32]),
+            merkle_root: Hash256([0x22; 32]),
+            state_root: Hash256([0x33; 32]),
+            timestamp: 0x0506_0708_090a_0b0c,
+            bits: 0x1d00_ffff,
+            nonce: 0xdead_beef,
+        };
+        let bytes = header.encode();
+        assert_eq!(BlockHeader::decode(&bytes), header);
+        // Little-endian integers, with the nonce in the last four bytes.
+        assert_eq!(bytes[4..8], [0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(bytes[104..112], [0x0c, 0x0b, 0x0a, 0x09, 0x08, 0x07, 0x06, 0x05]);
+        assert_eq!(bytes[HEADER_LEN - 4..], 0xdead_beefu32.to_le_bytes());
+        let bytes: [u8; HEADER_LEN] = std::array::from_fn(|i| i as u8);
+        assert_eq!(BlockHeader::decode(&bytes).encode(), bytes);
+    }
+
+    #[test]
+    fn coinbases_claim_at_most_the_subsidy_plus_fees() {
+        let params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        let key = SigningKey::from_seed(&[1; 32]);
+        let pay = |amount| {
+            vec![TxOutput {
+                recipient: Address::of(&key),
+                amount,
+            }]
+        };
+        let payment = Transaction::new_signed(&key, vec![], pay(10), 3, 0);
+        let block = |coinbase: Transaction| Block {
+            header: BlockHeader {
+                height: 1,
+                ..Block::genesis(&params).header
+            },
+            transactions: vec![coinbase, payment.clone()],
+        };
+        assert_eq!(block(Transaction::coinbase(1, pay(53))).validate_coinbase(&params), Ok(50));
+        assert_eq!(block(Transaction::coinbase(1, pay(20))).validate_coinbase(&params), Ok(17));
+        assert_eq!(
+            block(Transaction::coinbase(1, pay(54))).validate_coinbase(&params),
+            Err(ChainError::ExcessiveCoinbase {
+                height: 1,
+                claimed: 54,
+                allowed: 53,
+            })
+        );
+        // The extra nonce in the upper bits is free; the height in the lower ones is not.
+        let extra = Transaction::coinbase_with_extra_nonce(1, 7, pay(53));
+        assert_eq!(block(extra).validate_coinbase(&params), Ok(50));
+        for coinbase in [Transaction::coinbase(2, pay(53)), Transaction::coinbase_with_extra_nonce(0, 1, pay(53))] {
+            assert!(matches!(block(coinbase).validate_coinbase(&params), Err(ChainError::BadCoinbase { height: 1, .. })));
+        }
+    }
+}
@@ -0,0 +1,632 @@
+// Chain of blocks and the operations on it
+
+use crate::account::Account;
+use crate::block::{self, current_timestamp, Block, BlockHeader, HEADER_LEN};
+use crate::error::ChainError;
+use crate::fee::{self, FeeRate};
+use crate::hash::Hash256;
+use crate::headers;
+use crate::mempool::Mempool;
+use crate::merkle::{InclusionProof, MerkleBranch};
+use crate::miner::{self, MinerConfig};
+use crate::params::ChainParams;
+use crate::pow::{self, PowAlgorithm};
+use crate::retarget::{self, Retarget};
+use crate::state::ChainState;
+use crate::target::Target;
+use crate::transaction::{Address, OutPoint, Transaction, TxOutput};
+use crate::uint::U256;
+use crate::utxo::Utxo;
+use crate::versionbits::{self, DeploymentState, DeploymentStates};
+use std::slice;
+
+// Blockchain structure
+#[derive(Debug)]
+pub struct Blockchain<P: PowAlgorithm> {
+    pub chain: Vec<Block>,
+    /// Transactions waiting to be mined on top of `state`.
+    pub mempool: Mempool,
+    /// Ledger state after the blocks in `chain`, in the model `params.state_model` names.
+    pub state: ChainState,
+    /// Coins issued by the blocks in `chain`: the genesis allocations plus the
+    /// subsidy every coinbase claimed.
+    pub supply: u64,
+    pub params: ChainParams,
+    pub pow: P,
+    /// How new blocks are mined: threads, cancellation, deadline and progress reports.
+    pub miner: MinerConfig,
+    /// The deployment states `chain` has reached, updated as blocks are added.
+    deployments: DeploymentStates,
+}
+
+impl<P: PowAlgorithm> Blockchain<P> {
+    /// Creates a new Blockchain with a genesis block, mined with the given proof-of-work
+    /// algorithm against the target encoded in compact form by `bits`.
+    pub fn new(pow: P, bits: u32) -> Result<Self, ChainError> {
+        let params = ChainParams::new(pow.kind(), bits);
+        Self::with_params(pow, params)
+    }
+
+    /// Creates a new Blockchain with a genesis block under the given chain parameters.
+    pub fn with_params(pow: P, params: ChainParams) -> Result<Self, ChainError> {
+        pow::check_schedule(&pow, &params)?;
+        if Target::from_compact(params.bits).is_none() {
+            return Err(ChainError::InvalidParams("genesis target is not a valid compact target"));
+        }
+        if Target::from_compact(params.pow_limit).is_none() {
+            return Err(ChainError::InvalidParams("proof-of-work limit is not a valid compact target"));
+        }
+        if params.target_block_time == 0 {
+            return Err(ChainError::InvalidParams("target block time must be positive"));
+        }
+        match params.retarget {
+            Retarget::Window { interval } if interval < 2 => {
+                return Err(ChainError::InvalidParams("retarget interval must span at least two blocks"));
+            }
+            Retarget::Lwma { window: 0 } => return Err(ChainError::InvalidParams("LWMA window must be positive")),
+            Retarget::Asert { half_life: 0 } => {
+                return Err(ChainError::InvalidParams("ASERT half-life must be positive"));
+            }
+            _ => {}
+        }
+        if params.upgrades.iter().any(|upgrade| upgrade.bits.is_some_and(|bits| Target::from_compact(bits).is_none())) {
+            return Err(ChainError::InvalidParams("upgrade target is not a valid compact target"));
+        }
+        if params.signal_window == 0 || params.signal_threshold > params.signal_window {
+            return Err(ChainError::InvalidParams("signal threshold must be within a non-empty window"));
+        }
+        for (i, deployment) in params.deployments.iter().enumerate() {
+            if deployment.bit > versionbits::MAX_BIT {
+                return Err(ChainError::InvalidParams("deployment bit is outside the version bits"));
+            }
+            if params.deployments[..i].iter().any(|other| other.bit == deployment.bit) {
+                return Err(ChainError::InvalidParams("two deployments share a version bit"));
+            }
+        }
+        let supply = params
+            .genesis_outputs
+            .iter()
+            .try_fold(0u64, |total, output| total.checked_add(output.amount))
+            .filter(|supply| *supply <= params.max_supply)
+            .ok_or(ChainError::InvalidParams("genesis allocations exceed the maximum supply"))?;
+        let genesis_block = Block::genesis(&params);
+        let mut state = ChainState::new(&params);
+        state.connect(0, &genesis_block.transactions)?;
+        let mut deployments = DeploymentStates::default();
+        deployments.update(&params, slice::from_ref(&genesis_block));
+        Ok(Blockchain {
+            chain: vec![genesis_block],
+            mempool: Mempool::default(),
+            state,
+            supply,
+            params,
+            pow,
+            miner: MinerConfig::default(),
+            deployments,
+        })
+    }
+
+    /// Rebuilds a chain from blocks loaded from disk or received from a peer,
+    /// rejecting it unless every block validates.
+    pub fn from_blocks(pow: P, params: ChainParams, blocks: Vec<Block>) -> Result<Self, ChainError> {
+        let mut blockchain = Blockchain {
+            chain: blocks,
+            mempool: Mempool::default(),
+            state: ChainState::new(&params),
+            supply: 0,
+            params,
+            pow,
+            miner: MinerConfig::default(),
+            deployments: DeploymentStates::default(),
+        };
+        (blockchain.state, blockchain.supply) = blockchain.replay()?;
+        blockchain.deployments.update(&blockchain.params, &blockchain.chain);
+        Ok(blockchain)
+    }
+
+    /// Mines a block carrying the given transactions and adds it to the blockchain,
+    /// led by a coinbase paying `miner` the block's subsidy plus the transactions' fees.
+    ///
+    /// The transactions must be signed and apply in order to the current state, as
+    /// checked by `ChainState::connect`, and the block must fit the size limit;
+    /// nothing is mined otherwise. The subsidy is cut short rather than exceed the
+    /// maximum supply. Mined transactions and any conflicting with them leave the mempool.
+    ///
+    /// When no header nonce works, the timestamp is refreshed or the coinbase's extra
+    /// nonce is bumped and the search starts over. It only fails with `NonceSpaceExhausted`
+    /// once every extra nonce has been tried at the same timestamp.
+    pub fn add_block(&mut self, miner: Address, mut transactions: Vec<Transaction>) -> Result<(), ChainError> {
+        if let Some(transaction) = transactions.iter().find(|transaction| transaction.is_coinbase()) {
+            return Err(ChainError::UnexpectedCoinbase(transaction.id()));
+        }
+        if let Some(transaction) = transactions.iter().find(|transaction| !transaction.verify_signature()) {
+            return Err(ChainError::InvalidSignature(transaction.id()));
+        }
+        let previous_block = self.chain.last().ok_or(ChainError::EmptyChain)?;
+        // Always stamp a block after the median time past, even if the clock stepped back.
+        let timestamp = current_timestamp()?.max(block::median_time_past(&self.chain) + 1);
+
+        let height = self.chain.len() as u32;
+
+        let bits = self.next_bits()?;
+        let version = self.deployments.next_version(&self.params);
+
+        let previous_hash = previous_block.calculate_hash(&self.pow_at(height - 1)?);
+        let rules = self.params.rules_at(height);
+        let subsidy = self.params.subsidy(height).min(self.params.max_supply.saturating_sub(self.supply));
+        let fees = transactions
+            .iter()
+            .fold(0u64, |total, transaction| total.saturating_add(transaction.fee));
+        let reward = subsidy.saturating_add(fees);
+        let outputs = if reward > 0 {
+            vec![TxOutput {
+                recipient: miner,
+                amount: reward,
+            }]
+        } else {
+            vec![]
+        };
+        transactions.insert(0, Transaction::coinbase(height, outputs));
+        let size = HEADER_LEN + transactions.iter().map(Transaction::size).sum::<usize>();
+        if size > rules.max_block_size {
+            return Err(ChainError::BlockTooLarge {
+                height,
+                size,
+                max_size: rules.max_block_size,
+            });
+        }
+
+        let mut header = BlockHeader {
+            version,
+            height,
+            previous_hash,
+            merkle_root: Hash256::ZERO,
+            state_root: Hash256::ZERO,
+            timestamp,
+            bits,
+            nonce: 0,
+        };
+        let mut extra_nonce: u32 = 0;
+        let header = loop {
+            let undo = self.state.connect(height, &transactions)?;
+            header.merkle_root = block::compute_merkle_root(&transactions);
+            header.state_root = self.state.state_root();
+            match self.mine_block(header) {
+                Ok((header, _)) => break header,
+                Err(ChainError::NonceSpaceExhausted { .. }) => {
+                    // Every nonce failed for this header, so change it: move the
+                    // timestamp up to the clock if it has advanced, otherwise bump
+                    // the coinbase's extra nonce.
+                    self.state.disconnect(undo);
+                    let now = current_timestamp()?;
+                    if now > header.timestamp {
+                        header.timestamp = now;
+                    } else {
+                        extra_nonce = extra_nonce.checked_add(1)
+                            .ok_or(ChainError::NonceSpaceExhausted { height })?;
+                        let outputs = transactions[0].outputs.clone();
+                        transactions[0] = Transaction::coinbase_with_extra_nonce(height, extra_nonce, outputs);
+                    }
+                }
+                Err(err) => {
+                    self.state.disconnect(undo);
+                    return Err(err);
+                }
+            }
+        };
+
+        self.mempool.remove_confirmed(&transactions, &self.state, height + 1);
+        self.chain.push(Block { header, transactions });
+        self.deployments.update(&self.params, &self.chain);
+        self.supply += subsidy;
+        Ok(())
+    }
+
+    /// Mines a block by finding a nonce for `header` whose hash meets the target
+    /// encoded by its `bits`, with the algorithm scheduled at its height.
+    ///
+    /// Fails with `NonceSpaceExhausted` once every nonce up to `MinerConfig::max_nonce`
+    /// has been tried, or when `miner` cancels the job or its deadline passes.
+    fn mine_block(&self, header: BlockHeader) -> Result<(BlockHeader, Hash256), ChainError> {
+        miner::mine(&self.pow_at(header.height)?, header, &self.miner)
+    }
+
+    /// The proof-of-work algorithm the block at `height` is hashed with; see `ChainParams::rules_at`.
+    pub fn pow_at(&self, height: u32) -> Result<P, ChainError> {
+        pow::at_height(&self.pow, &self.params, height)
+    }
+
+    /// Returns the compact target the next block must meet under the retargeting rule.
+    pub fn next_bits(&self) -> Result<u32, ChainError> {
+        retarget::next_bits(&self.params, &self.chain)
+    }
+
+    /// Verifies the whole chain: the genesis block, then every block against its
+    /// parent, against the target retargeting expects at its height, against the
+    /// timestamp bounds of `block::check_timestamp` and against the ledger state
+    /// left by the blocks before it, each under the rules in force at its height;
+    /// see `ChainParams::rules_at`.
+    pub fn validate(&self) -> Result<(), ChainError> {
+        self.replay().map(|_| ())
+    }
+
+    /// Validates the chain from genesis, returning the ledger state it leaves and
+    /// the coins it issued.
+    fn replay(&self) -> Result<(ChainState, u64), ChainError> {
+        pow::check_schedule(&self.pow, &self.params)?;
+        let genesis = self.chain.first().ok_or(ChainError::EmptyChain)?;
+        if *genesis != Block::genesis(&self.params) {
+            return Err(ChainError::BadGenesis);
+        }
+        let mut state = ChainState::new(&self.params);
+        state.connect(0, &genesis.transactions)?;
+        let mut supply = genesis
+            .transactions
+            .iter()
+            .flat_map(|transaction| &transaction.outputs)
+            .map(|output| output.amount as u128)
+            .sum::<u128>();
+        if supply > self.params.max_supply as u128 {
+            return Err(ChainError::SupplyExceeded {
+                height: 0,
+                supply,
+                max_supply: self.params.max_supply,
+            });
+        }
+        let mut parent_hash = genesis.calculate_hash(&self.pow_at(0)?);
+        let now = current_timestamp()?;
+        for height in 1..self.chain.len() {
+            let block = &self.chain[height];
+            let pow = self.pow_at(height as u32)?;
+            parent_hash = block.validate_against(&self.chain[height - 1].header, &parent_hash, &pow)?;
+            retarget::check_bits(&self.params, &self.chain[..height], &block.header)?;
+            block::check_timestamp(&self.params, &self.chain[..height], &block.header, now)?;
+            let max_size = self.params.rules_at(block.height()).max_block_size;
+            if block.size() > max_size {
+                return Err(ChainError::BlockTooLarge {
+                    height: block.height(),
+                    size: block.size(),
+                    max_size,
+                });
+            }
+            supply += block.validate_coinbase(&self.params)? as u128;
+            if supply > self.params.max_supply as u128 {
+                return Err(ChainError::SupplyExceeded {
+                    height: block.height(),
+                    supply,
+                    max_supply: self.params.max_supply,
+                });
+            }
+            state.connect(block.height(), &block.transactions)?;
+            let state_root = state.state_root();
+            if block.header.state_root != state_root {
+                return Err(ChainError::BadStateRoot {
+                    height: block.height(),
+                    expected: state_root,
+                    actual: block.header.state_root,
+                });
+            }
+        }
+        Ok((state, supply as u64))
+    }
+
+    /// State of the deployment called `name` for the next block, or `None` if the
+    /// parameters define no such deployment; see `versionbits::state`.
+    pub fn deployment_state(&self, name: &str) -> Option<DeploymentState> {
+        let index = self.params.deployments.iter().position(|deployment| deployment.name == name)?;
+        Some(self.deployments.get(index))
+    }
+
+    /// Sums the expected work of every block after genesis; see `headers::total_work`.
+    pub fn total_work(&self) -> U256 {
+        headers::total_work(&self.chain)
+    }
+
+    /// Every block header, genesis first, for peers syncing headers and for light
+    /// clients; see `HeaderChain::from_headers`.
+    pub fn headers(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.chain.iter().map(|block| &block.header)
+    }
+
+    /// Builds a proof that the transaction with id `tx_id`, as signed in the
+    /// chain, is committed to by the header of the block containing it; see
+    /// `merkle::verify_inclusion`.
+    pub fn prove_inclusion(&self, tx_id: &Hash256) -> Result<InclusionProof, ChainError> {
+        for block in &self.chain {
+            if let Some(index) = block.transactions.iter().position(|transaction| transaction.id() == *tx_id) {
+                let ids: Vec<Hash256> = block.transactions.iter().map(Transaction::witness_id).collect();
+                let branch = MerkleBranch::new(&ids, index).ok_or(ChainError::UnknownTransaction(*tx_id))?;
+                return Ok(InclusionProof {
+                    transaction: block.transactions[index].clone(),
+                    header: block.header,
+                    branch,
+                });
+            }
+        }
+        Err(ChainError::UnknownTransaction(*tx_id))
+    }
+
+    /// Unspent outputs paying `address`, mature or not, ordered by outpoint.
+    /// Account chains have none.
+    pub fn unspent_outputs(&self, address: &Address) -> Vec<(OutPoint, Utxo)> {
+        self.state.utxos().map(|utxos| utxos.unspent_for(address)).unwrap_or_default()
+    }
+
+    /// Coins `address` may spend in the next block: its mature unspent outputs or
+    /// its account balance, including rewards that mature in that block.
+    pub fn balance(&self, address: &Address) -> u64 {
+        self.state.balance(address, self.chain.len() as u32)
+    }
+
+    /// Confirmed state of the account at `address`, or `None` on a UTXO chain.
+    pub fn account(&self, address: &Address) -> Option<Account> {
+        self.state.account(address)
+    }
+
+    /// Adds a transaction to the mempool; see `Mempool::insert` for what it rejects.
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<(), ChainError> {
+        let height = self.chain.len() as u32;
+        self.mempool.insert(transaction, &self.state, height, current_timestamp()?)
+    }
+
+    /// Fee rate a transaction should pay to be mined within `target_blocks` blocks,
+    /// judged from the rates recent blocks accepted and the mempool backlog; see `fee::estimate`.
+    pub fn estimate_fee(&self, target_blocks: u32) -> FeeRate {
+        fee::estimate(&self.params, &self.chain, &self.mempool, target_blocks)
+    }
+
+    /// Mines a new block rewarding `miner`, filled from the mempool by fee rate
+    /// up to the block size limit; see `Mempool::build_template`.
+    ///
+    /// Does nothing if the mempool is empty. On failure the transactions stay in the mempool.
+    pub fn mine_and_add_block(&mut self, miner: Address) -> Result<(), ChainError> {
+        let height = self.chain.len() as u32;
+        self.mempool.expire(current_timestamp()?, &self.state, height);
+        let coinbase = Transaction::coinbase(height, vec![TxOutput { recipient: miner, amount: 0 }]);
+        let max_bytes = self.params.rules_at(height).max_block_size.saturating_sub(HEADER_LEN + coinbase.size());
+        let transactions = self.mempool.build_template(&self.state, height, max_bytes);
+        if transactions.is_empty() {
+            return Ok(());
+        }
+        self.add_block(miner, transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::headers::HeaderChain;
+    use crate::params::{Rules, Upgrade};
+    use crate::pow::{AnyPow, Blake3, PowKind, Sha256d};
+    use crate::state::StateModel;
+
+    #[test]
+    fn rejects_retargeting_that_cannot_work() {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.target_block_time = 0;
+        assert!(matches!(Blockchain::with_params(Sha256d, params), Err(ChainError::InvalidParams(_))));
+        for retarget in [Retarget::Window { interval: 1 }, Retarget::Lwma { window: 0 }, Retarget::Asert { half_life: 0 }] {
+            let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+            params.retarget = retarget;
+            assert!(matches!(Blockchain::with_params(Sha256d, params), Err(ChainError::InvalidParams(_))));
+        }
+    }
+
+    #[test]
+    fn rejects_blocks_stamped_far_in_the_future() {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.retarget = Retarget::Asert { half_life: 3600 };
+        let mut blockchain = Blockchain::with_params(Sha256d, params.clone()).unwrap();
+        for _ in 0..2 {
+            blockchain.add_block(Address::default(), vec![]).unwrap();
+        }
+        let mut blocks = blockchain.chain.clone();
+        blocks[2].header.timestamp = 1 << 40;
+        blocks[2].header = miner::mine(&Sha256d, blocks[2].header, &MinerConfig::default()).unwrap().0;
+        let result = Blockchain::from_blocks(Sha256d, params.clone(), blocks.clone());
+        assert!(matches!(result, Err(ChainError::TimestampTooFarAhead { height: 2, .. })));
+        let headers = blocks.iter().map(|block| block.header).collect();
+        let result = HeaderChain::from_headers(Sha256d, params, headers);
+        assert!(matches!(result, Err(ChainError::TimestampTooFarAhead { height: 2, .. })));
+    }
+
+    #[test]
+    fn blocks_are_stamped_after_the_median_time_past_when_the_clock_lags() {
+        let mut blockchain = Blockchain::new(Sha256d, 0x207f_ffff).unwrap();
+        for _ in 0..2 {
+            blockchain.add_block(Address::default(), vec![]).unwrap();
+        }
+        // Restamp both blocks an hour ahead, within the drift bound, as if the clock stepped back.
+        let ahead = current_timestamp().unwrap() + 3_600;
+        for height in 1..3 {
+            let previous_hash = blockchain.chain[height - 1].calculate_hash(&Sha256d);
+            let header = BlockHeader {
+                previous_hash,
+                timestamp: ahead + height as u64 - 1,
+                ..blockchain.chain[height].header
+            };
+            blockchain.chain[height].header = miner::mine(&Sha256d, header, &MinerConfig::default()).unwrap().0;
+        }
+        assert_eq!(block::median_time_past(&blockchain.chain), ahead);
+        // One second past the median, which here is the parent's own timestamp.
+        blockchain.add_block(Address::default(), vec![]).unwrap();
+        assert_eq!(blockchain.chain[3].header.timestamp, ahead + 1);
+        blockchain.validate().unwrap();
+    }
+
+    #[test]
+    fn account_chains_report_balances_and_nonces() {
+        let (alice, bob) = (SigningKey::from_seed(&[1; 32]), SigningKey::from_seed(&[2; 32]));
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.state_model = StateModel::Account;
+        params.genesis_outputs = vec![TxOutput {
+            recipient: Address::of(&alice),
+            amount: 100,
+        }];
+        let mut blockchain = Blockchain::with_params(Sha256d, params).unwrap();
+        assert_eq!(blockchain.account(&Address::of(&alice)), Some(Account { balance: 100, nonce: 0 }));
+        let payment = TxOutput {
+            recipient: Address::of(&bob),
+            amount: 10,
+        };
+        // A wallet signs the next transaction with the nonce the chain reports.
+        for _ in 0..2 {
+            let nonce = blockchain.account(&Address::of(&alice)).unwrap().nonce;
+            let transfer = Transaction::new_signed(&alice, vec![], vec![payment], 1, nonce);
+            blockchain.add_block(Address::default(), vec![transfer]).unwrap();
+        }
+        assert_eq!(blockchain.account(&Address::of(&alice)), Some(Account { balance: 78, nonce: 2 }));
+        assert_eq!(blockchain.account(&Address::of(&bob)), Some(Account { balance: 20, nonce: 0 }));
+
+        let utxo_chain = Blockchain::new(Sha256d, 0x207f_ffff).unwrap();
+        assert_eq!(utxo_chain.account(&Address::of(&alice)), None);
+    }
+
+    /// SHA-256d, except that no header committing to `blocked` meets any target.
+    #[derive(Debug, Clone)]
+    struct Blocking {
+        blocked: Hash256,
+    }
+
+    impl PowAlgorithm for Blocking {
+        type Midstate = [u8; HEADER_LEN];
+
+        fn kind(&self) -> PowKind {
+            PowKind::Sha256d
+        }
+
+        fn hash(&self, header: &[u8]) -> Hash256 {
+            if header[40..72] == self.blocked.0 {
+                Hash256([0xff; 32])
+            } else {
+                Sha256d.hash(header)
+            }
+        }
+
+        fn midstate(&self, header: &[u8; HEADER_LEN]) -> Self::Midstate {
+            *header
+        }
+
+        fn hash_with_nonce(&self, midstate: &mut Self::Midstate, nonce: u32) -> Hash256 {
+            midstate[HEADER_LEN - 4..].copy_from_slice(&nonce.to_le_bytes());
+            self.hash(midstate)
+        }
+    }
+
+    #[test]
+    fn exhausting_the_nonces_moves_on_to_the_next_extra_nonce() {
+        let miner = Address::of(&SigningKey::from_seed(&[1; 32]));
+        let first = Transaction::coinbase(1, vec![TxOutput { recipient: miner, amount: 50 }]);
+        let blocked = block::compute_merkle_root(slice::from_ref(&first));
+        let mut blockchain = Blockchain::new(Blocking { blocked }, 0x207f_ffff).unwrap();
+        // A chance of 2^-64 that none of 64 nonces meets the target at the next extra nonce.
+        blockchain.miner.max_nonce = 63;
+        blockchain.add_block(miner, vec![]).unwrap();
+
+        let coinbase = &blockchain.chain[1].transactions[0];
+        assert_eq!(*coinbase, Transaction::coinbase_with_extra_nonce(1, 1, first.outputs.clone()));
+        assert_ne!(blockchain.chain[1].header.merkle_root, blocked);
+        assert_eq!(blockchain.chain[1].header.merkle_root, block::compute_merkle_root(slice::from_ref(coinbase)));
+        Blockchain::from_blocks(Sha256d, blockchain.params.clone(), blockchain.chain).unwrap();
+    }
+
+    #[test]
+    fn upgrades_switch_the_algorithm_and_reset_the_target() {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.upgrades = vec![Upgrade {
+            name: "blake3",
+            height: 3,
+            rules: Rules {
+                pow: PowKind::Blake3,
+                ..params.rules
+            },
+            bits: Some(0x200f_ffff),
+        }];
+        assert_eq!(
+            Blockchain::with_params(Sha256d, params.clone()).err(),
+            Some(ChainError::PowAlgorithmMismatch {
+                expected: PowKind::Blake3,
+                actual: PowKind::Sha256d,
+            })
+        );
+        let pow = AnyPow::new(PowKind::Sha256d).unwrap();
+        let mut blockchain = Blockchain::with_params(pow, params.clone()).unwrap();
+        for _ in 0..5 {
+            blockchain.add_block(Address::default(), vec![]).unwrap();
+        }
+        let kinds: Vec<PowKind> = (0..6).map(|height| blockchain.pow_at(height).unwrap().kind()).collect();
+        assert_eq!(kinds[..3], [PowKind::Sha256d; 3]);
+        assert_eq!(kinds[3..], [PowKind::Blake3; 3]);
+        let bits: Vec<u32> = blockchain.chain.iter().map(|block| block.header.bits).collect();
+        assert_eq!(bits, [0x207f_ffff, 0x207f_ffff, 0x207f_ffff, 0x200f_ffff, 0x200f_ffff, 0x200f_ffff]);
+        for (height, block) in blockchain.chain.iter().enumerate().skip(1) {
+            let hash = block.calculate_hash(&AnyPow::new(kinds[height]).unwrap());
+            assert!(block::is_valid_hash(&hash, block.header.bits), "height {}", height);
+        }
+
+        // Full nodes and light clients both follow the chain across the upgrade.
+        Blockchain::from_blocks(pow, params.clone(), blockchain.chain.clone()).unwrap();
+        let headers: Vec<BlockHeader> = blockchain.chain.iter().map(|block| block.header).collect();
+        let light = HeaderChain::from_headers(pow, params.clone(), headers.clone()).unwrap();
+        assert_eq!(light.tip_hash(), blockchain.chain[5].calculate_hash(&Blake3));
+
+        // The first block after the upgrade must carry the reset target...
+        let mut stale = headers.clone();
+        stale[3].bits = 0x207f_ffff;
+        stale[3] = miner::mine(&Blake3, stale[3], &MinerConfig::default()).unwrap().0;
+        assert_eq!(
+            HeaderChain::from_headers(pow, params.clone(), stale[..4].to_vec()).err(),
+            Some(ChainError::BadDifficulty {
+                height: 3,
+                expected: 0x200f_ffff,
+                actual: 0x207f_ffff,
+            })
+        );
+        // ...and be hashed with the new algorithm.
+        let mut old_pow = headers;
+        old_pow[3].nonce = (0..)
+            .find(|&nonce| {
+                let header = BlockHeader { nonce, ..old_pow[3] };
+                block::is_valid_hash(&header.calculate_hash(&Sha256d), header.bits)
+                    && !block::is_valid_hash(&header.calculate_hash(&Blake3), header.bits)
+            })
+            .unwrap();
+        assert_eq!(
+            HeaderChain::from_headers(pow, params, old_pow[..4].to_vec()).err(),
+            Some(ChainError::BadPow { height: 3 })
+        );
+    }
+
+    #[test]
+    fn issuance_stops_at_the_maximum_supply() {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.max_supply = 120;
+        let mut blockchain = Blockchain::with_params(Sha256d, params.clone()).unwrap();
+        for _ in 0..4 {
+            blockchain.add_block(Address::default(), vec![]).unwrap();
+        }
+        // The third coinbase is cut short at the cap and the fourth claims nothing.
+        let rewards: Vec<Option<u64>> =
+            blockchain.chain[1..].iter().map(|block| block.transactions[0].total_output()).collect();
+        assert_eq!(rewards, [Some(50), Some(50), Some(20), Some(0)]);
+        assert_eq!(blockchain.supply, 120);
+        Blockchain::from_blocks(Sha256d, params.clone(), blockchain.chain.clone()).unwrap();
+
+        // Full subsidies stay within each block's allowance but not within the cap.
+        params.max_supply = 21_000_000;
+        let mut uncapped = Blockchain::with_params(Sha256d, params.clone()).unwrap();
+        for _ in 0..3 {
+            uncapped.add_block(Address::default(), vec![]).unwrap();
+        }
+        params.max_supply = 120;
+        assert_eq!(
+            Blockchain::from_blocks(Sha256d, params, uncapped.chain).err(),
+            Some(ChainError::SupplyExceeded {
+                height: 3,
+                supply: 150,
+                max_supply: 120,
+            })
+        );
+    }
+}
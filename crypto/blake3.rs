@@ -0,0 +1,401 @@
+// BLAKE3 in its default hashing mode, following the reference implementation
+
+const OUT_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    // Columns
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    // Diagonals
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+fn permute(m: &mut [u32; 16]) {
+    let mut permuted = [0; 16];
+    for i in 0..16 {
+        permuted[i] = m[MSG_PERMUTATION[i]];
+    }
+    *m = permuted;
+}
+
+fn compress(chaining_value: &[u32; 8], block_words: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    let mut state = [
+        chaining_value[0],
+        chaining_value[1],
+        chaining_value[2],
+        chaining_value[3],
+        chaining_value[4],
+        chaining_value[5],
+        chaining_value[6],
+        chaining_value[7],
+        IV[0],
+        IV[1],
+        IV[2],
+        IV[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_len,
+        flags,
+    ];
+    let mut block = *block_words;
+
+    for i in 0..7 {
+        round(&mut state, &block);
+        if i < 6 {
+            permute(&mut block);
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+    state
+}
+
+fn first_8_words(compression_output: [u32; 16]) -> [u32; 8] {
+    compression_output[0..8].try_into().unwrap()
+}
+
+fn words_from_le_bytes(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0u32; 16];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    words
+}
+
+// The input to a compression that has not been performed yet, kept around so
+// the root node can be finalized with the ROOT flag.
+struct Output {
+    input_chaining_value: [u32; 8],
+    block_words: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8_words(compress(
+            &self.input_chaining_value,
+            &self.block_words,
+            self.counter,
+            self.block_len,
+            self.flags,
+        ))
+    }
+
+    fn root_hash(&self) -> [u8; OUT_LEN] {
+        let words = compress(&self.input_chaining_value, &self.block_words, 0, self.block_len, self.flags | ROOT);
+        let mut out = [0u8; OUT_LEN];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ChunkState {
+    chaining_value: [u32; 8],
+    chunk_counter: u64,
+    block: [u8; BLOCK_LEN],
+    block_len: u8,
+    blocks_compressed: u8,
+}
+
+impl ChunkState {
+    fn new(chunk_counter: u64) -> Self {
+        ChunkState {
+            chaining_value: IV,
+            chunk_counter,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            blocks_compressed: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks_compressed as usize + self.block_len as usize
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 { CHUNK_START } else { 0 }
+    }
+
+    fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // Only compress a full block once we know more input follows, so
+            // the last block of the chunk can carry CHUNK_END.
+            if self.block_len as usize == BLOCK_LEN {
+                let block_words = words_from_le_bytes(&self.block);
+                self.chaining_value = first_8_words(compress(
+                    &self.chaining_value,
+                    &block_words,
+                    self.chunk_counter,
+                    BLOCK_LEN as u32,
+                    self.start_flag(),
+                ));
+                self.blocks_compressed += 1;
+                self.block = [0; BLOCK_LEN];
+                self.block_len = 0;
+            }
+
+            let want = BLOCK_LEN - self.block_len as usize;
+            let take = want.min(input.len());
+            self.block[self.block_len as usize..][..take].copy_from_slice(&input[..take]);
+            self.block_len += take as u8;
+            input = &input[take..];
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output {
+            input_chaining_value: self.chaining_value,
+            block_words: words_from_le_bytes(&self.block),
+            counter: self.chunk_counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+fn parent_output(left_child_cv: [u32; 8], right_child_cv: [u32; 8]) -> Output {
+    let mut block_words = [0; 16];
+    block_words[..8].copy_from_slice(&left_child_cv);
+    block_words[8..].copy_from_slice(&right_child_cv);
+    Output {
+        input_chaining_value: IV,
+        block_words,
+        counter: 0,
+        block_len: BLOCK_LEN as u32,
+        flags: PARENT,
+    }
+}
+
+/// Incremental BLAKE3 hasher producing the default 32-byte output.
+#[derive(Clone)]
+pub struct Blake3 {
+    chunk_state: ChunkState,
+    cv_stack: [[u32; 8]; 54],
+    cv_stack_len: u8,
+}
+
+impl Blake3 {
+    /// Creates a hasher in the initial state.
+    pub fn new() -> Self {
+        Blake3 {
+            chunk_state: ChunkState::new(0),
+            cv_stack: [[0; 8]; 54],
+            cv_stack_len: 0,
+        }
+    }
+
+    fn push_stack(&mut self, cv: [u32; 8]) {
+        self.cv_stack[self.cv_stack_len as usize] = cv;
+        self.cv_stack_len += 1;
+    }
+
+    fn pop_stack(&mut self) -> [u32; 8] {
+        self.cv_stack_len -= 1;
+        self.cv_stack[self.cv_stack_len as usize]
+    }
+
+    // Merges completed subtrees: one merge per trailing zero bit in the new
+    // total number of chunks.
+    fn add_chunk_chaining_value(&mut self, mut new_cv: [u32; 8], mut total_chunks: u64) {
+        while total_chunks & 1 == 0 {
+            new_cv = parent_output(self.pop_stack(), new_cv).chaining_value();
+            total_chunks >>= 1;
+        }
+        self.push_stack(new_cv);
+    }
+
+    /// Feeds more bytes into the hasher.
+    pub fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            if self.chunk_state.len() == CHUNK_LEN {
+                let chunk_cv = self.chunk_state.output().chaining_value();
+                let total_chunks = self.chunk_state.chunk_counter + 1;
+                self.add_chunk_chaining_value(chunk_cv, total_chunks);
+                self.chunk_state = ChunkState::new(total_chunks);
+            }
+
+            let want = CHUNK_LEN - self.chunk_state.len();
+            let take = want.min(input.len());
+            self.chunk_state.update(&input[..take]);
+            input = &input[take..];
+        }
+    }
+
+    /// Returns the 32-byte root hash.
+    pub fn finalize(&self) -> [u8; OUT_LEN] {
+        let mut output = self.chunk_state.output();
+        let mut parent_nodes_remaining = self.cv_stack_len as usize;
+        while parent_nodes_remaining > 0 {
+            parent_nodes_remaining -= 1;
+            output = parent_output(self.cv_stack[parent_nodes_remaining], output.chaining_value());
+        }
+        output.root_hash()
+    }
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Incremental BLAKE3 hasher for inputs of at most one 1024-byte chunk, such as
+/// block headers.
+///
+/// It keeps only the chunk's chaining value and partial block, without the
+/// subtree stack of `Blake3`, so it is about a tenth of the size and cheap to copy.
+#[derive(Clone, Copy)]
+pub struct ChunkHasher {
+    chunk_state: ChunkState,
+}
+
+impl ChunkHasher {
+    /// Creates a hasher in the initial state.
+    pub fn new() -> Self {
+        ChunkHasher {
+            chunk_state: ChunkState::new(0),
+        }
+    }
+
+    /// Feeds more bytes into the hasher.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input so far grows past one chunk.
+    pub fn update(&mut self, input: &[u8]) {
+        assert!(self.chunk_state.len() + input.len() <= CHUNK_LEN, "input longer than one chunk");
+        self.chunk_state.update(input);
+    }
+
+    /// The last `len` bytes of input, which are still in the block not compressed
+    /// yet, to change in place before `finalize`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is more than the bytes in that block.
+    pub fn tail_mut(&mut self, len: usize) -> &mut [u8] {
+        let end = self.chunk_state.block_len as usize;
+        &mut self.chunk_state.block[end - len..end]
+    }
+
+    /// Returns the 32-byte root hash, the same as `blake3` of the input.
+    pub fn finalize(&self) -> [u8; OUT_LEN] {
+        self.chunk_state.output().root_hash()
+    }
+}
+
+impl Default for ChunkHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// BLAKE3 of `data`.
+pub fn blake3(data: &[u8]) -> [u8; OUT_LEN] {
+    let mut hasher = Blake3::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The official test vectors, whose input is the bytes 0, 1, .., 250 repeated.
+    #[test]
+    fn official_test_vectors() {
+        let vectors = [
+            (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+            (63, "e9bc37a594daad83be9470df7f7b3798297c3d834ce80ba85d6e207627b7db7b"),
+            (64, "4eed7141ea4a5cd4b788606bd23f46e212af9cacebacdc7d1f4c6dc7f2511b98"),
+            (65, "de1e5fa0be70df6d2be8fffd0e99ceaa8eb6e8c93a63f2d8d1c30ecb6b263dee"),
+            (127, "d81293fda863f008c09e92fc382a81f5a0b4a1251cba1634016a0f86a6bd640d"),
+            (128, "f17e570564b26578c33bb7f44643f539624b05df1a76c81f30acd548c44b45ef"),
+            (129, "683aaae9f3c5ba37eaaf072aed0f9e30bac0865137bae68b1fde4ca2aebdcb12"),
+            (1023, "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11"),
+            (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+            (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+            (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+            (2049, "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030"),
+            (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+            (3073, "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3"),
+            (4096, "015094013f57a5277b59d8475c0501042c0b642e531b0a1c8f58d2163229e969"),
+            (4097, "9b4052b38f1c5fc8b1f9ff7ac7b27cd242487b3d890d15c96a1c25b8aa0fb995"),
+            (8193, "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b"),
+        ];
+        let input: Vec<u8> = (0..8193).map(|i| (i % 251) as u8).collect();
+        for (len, expected) in vectors {
+            let input = &input[..len];
+            assert_eq!(blake3(input).to_vec(), crate::crypto::hex(expected), "length {}", len);
+
+            let mut hasher = Blake3::new();
+            for piece in input.chunks(100) {
+                hasher.update(piece);
+            }
+            assert_eq!(hasher.finalize(), blake3(input), "length {} incremental", len);
+        }
+    }
+
+    #[test]
+    fn chunk_hasher_matches_the_full_hasher() {
+        assert_eq!(
+            blake3(b""),
+            crate::crypto::hex("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262")[..]
+        );
+        let input: Vec<u8> = (0..CHUNK_LEN).map(|i| (i % 251) as u8).collect();
+        for len in [0, 1, 63, 64, 65, 120, 1023, 1024] {
+            let mut hasher = ChunkHasher::new();
+            let (head, tail) = input[..len].split_at(len / 3);
+            hasher.update(head);
+            hasher.update(tail);
+            assert_eq!(hasher.finalize(), blake3(&input[..len]), "length {}", len);
+
+            let mut changed = input[..len].to_vec();
+            // Only the bytes of the last block can change; it is never empty but for no input.
+            let changes = (len - len.saturating_sub(1) / BLOCK_LEN * BLOCK_LEN).min(4);
+            for byte in &mut changed[len - changes..] {
+                *byte ^= 0xff;
+            }
+            hasher.tail_mut(changes).copy_from_slice(&changed[len - changes..]);
+            assert_eq!(hasher.finalize(), blake3(&changed), "length {} changed", len);
+        }
+    }
+}
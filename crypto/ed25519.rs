@@ -0,0 +1,109 @@
+// Ed25519 signatures (RFC 8032), backed by the ed25519-dalek crate
+
+use ed25519_dalek::{Signature, Signer, VerifyingKey};
+
+/// An Ed25519 private key, derived from a 32-byte seed.
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        SigningKey(ed25519_dalek::SigningKey::from_bytes(seed))
+    }
+
+    /// The 32-byte encoded public key.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.0.verifying_key().to_bytes()
+    }
+
+    /// Produces the deterministic 64-byte signature of `message`.
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKey").field("public_key", &self.public_key()).finish_non_exhaustive()
+    }
+}
+
+/// Checks an Ed25519 signature under the strict rules: non-canonical encodings
+/// and small-order keys or commitments are rejected, so every node agrees on
+/// which signatures are valid.
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    key.verify_strict(message, &Signature::from_bytes(signature)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hex;
+
+    // RFC 8032 section 7.1, tests 1 to 3: seed, public key, message, signature.
+    const VECTORS: [(&str, &str, &str, &str); 3] = [
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+        (
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        ),
+    ];
+
+    // The group order L, little-endian.
+    const ORDER: [u64; 4] = [0x5812_631a_5cf5_d3ed, 0x14de_f9de_a2f7_9cd6, 0, 0x1000_0000_0000_0000];
+
+    #[test]
+    fn rfc_8032_vectors() {
+        for (seed, public_key, message, signature) in VECTORS {
+            let key = SigningKey::from_seed(&hex(seed).try_into().unwrap());
+            assert_eq!(key.public_key().to_vec(), hex(public_key));
+            let message = hex(message);
+            let signature: [u8; 64] = hex(signature).try_into().unwrap();
+            assert_eq!(key.sign(&message), signature);
+            assert!(verify(&key.public_key(), &message, &signature));
+        }
+    }
+
+    #[test]
+    fn rejects_altered_and_non_canonical_signatures() {
+        let (seed, _, _, _) = VECTORS[2];
+        let key = SigningKey::from_seed(&hex(seed).try_into().unwrap());
+        let signature = key.sign(b"message");
+        assert!(!verify(&key.public_key(), b"massage", &signature));
+        let mut altered = signature;
+        altered[5] ^= 1;
+        assert!(!verify(&key.public_key(), b"message", &altered));
+        // s + L encodes the same scalar but is not canonical.
+        let mut high_s = signature;
+        let mut carry = 0u128;
+        for (i, chunk) in high_s[32..].chunks_exact_mut(8).enumerate() {
+            let sum = u64::from_le_bytes(chunk.try_into().unwrap()) as u128 + ORDER[i] as u128 + carry;
+            chunk.copy_from_slice(&(sum as u64).to_le_bytes());
+            carry = sum >> 64;
+        }
+        assert!(!verify(&key.public_key(), b"message", &high_s));
+        // The identity has small order: with R the identity and s = 0, a loose check
+        // would accept it as a signature on any message.
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let mut forged = [0u8; 64];
+        forged[..32].copy_from_slice(&identity);
+        assert!(!verify(&identity, b"message", &forged));
+    }
+}
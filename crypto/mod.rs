@@ -0,0 +1,16 @@
+// Hash primitives used by the chain
+
+pub mod blake3;
+pub mod ed25519;
+pub mod scrypt;
+pub mod sha256;
+pub mod sha512;
+
+/// Decodes a hex string, for the test vectors in this module's tests.
+#[cfg(test)]
+fn hex(digits: &str) -> Vec<u8> {
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect()
+}
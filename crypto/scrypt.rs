@@ -0,0 +1,221 @@
+// scrypt (RFC 7914) on top of HMAC-SHA256
+
+use super::sha256::Sha256;
+
+/// HMAC-SHA256 of `message` under `key`.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block_key = [0u8; 64];
+    if key.len() > 64 {
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        block_key[..32].copy_from_slice(&hasher.finalize());
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block_key.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(&block_key.map(|byte| byte ^ 0x5c));
+    outer.update(&inner.finalize());
+    outer.finalize()
+}
+
+/// PBKDF2 with HMAC-SHA256 as the PRF, filling `out`.
+pub fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    for (block_index, chunk) in out.chunks_mut(32).enumerate() {
+        let mut message = salt.to_vec();
+        message.extend_from_slice(&(block_index as u32 + 1).to_be_bytes());
+        let mut u = hmac_sha256(password, &message);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &u);
+            for (acc, byte) in t.iter_mut().zip(u) {
+                *acc ^= byte;
+            }
+        }
+        chunk.copy_from_slice(&t[..chunk.len()]);
+    }
+}
+
+fn salsa20_8(block: &mut [u32; 16]) {
+    let mut x = *block;
+    for _ in 0..4 {
+        // Columns
+        x[4] ^= x[0].wrapping_add(x[12]).rotate_left(7);
+        x[8] ^= x[4].wrapping_add(x[0]).rotate_left(9);
+        x[12] ^= x[8].wrapping_add(x[4]).rotate_left(13);
+        x[0] ^= x[12].wrapping_add(x[8]).rotate_left(18);
+        x[9] ^= x[5].wrapping_add(x[1]).rotate_left(7);
+        x[13] ^= x[9].wrapping_add(x[5]).rotate_left(9);
+        x[1] ^= x[13].wrapping_add(x[9]).rotate_left(13);
+        x[5] ^= x[1].wrapping_add(x[13]).rotate_left(18);
+        x[14] ^= x[10].wrapping_add(x[6]).rotate_left(7);
+        x[2] ^= x[14].wrapping_add(x[10]).rotate_left(9);
+        x[6] ^= x[2].wrapping_add(x[14]).rotate_left(13);
+        x[10] ^= x[6].wrapping_add(x[2]).rotate_left(18);
+        x[3] ^= x[15].wrapping_add(x[11]).rotate_left(7);
+        x[7] ^= x[3].wrapping_add(x[15]).rotate_left(9);
+        x[11] ^= x[7].wrapping_add(x[3]).rotate_left(13);
+        x[15] ^= x[11].wrapping_add(x[7]).rotate_left(18);
+        // Rows
+        x[1] ^= x[0].wrapping_add(x[3]).rotate_left(7);
+        x[2] ^= x[1].wrapping_add(x[0]).rotate_left(9);
+        x[3] ^= x[2].wrapping_add(x[1]).rotate_left(13);
+        x[0] ^= x[3].wrapping_add(x[2]).rotate_left(18);
+        x[6] ^= x[5].wrapping_add(x[4]).rotate_left(7);
+        x[7] ^= x[6].wrapping_add(x[5]).rotate_left(9);
+        x[4] ^= x[7].wrapping_add(x[6]).rotate_left(13);
+        x[5] ^= x[4].wrapping_add(x[7]).rotate_left(18);
+        x[11] ^= x[10].wrapping_add(x[9]).rotate_left(7);
+        x[8] ^= x[11].wrapping_add(x[10]).rotate_left(9);
+        x[9] ^= x[8].wrapping_add(x[11]).rotate_left(13);
+        x[10] ^= x[9].wrapping_add(x[8]).rotate_left(18);
+        x[12] ^= x[15].wrapping_add(x[14]).rotate_left(7);
+        x[13] ^= x[12].wrapping_add(x[15]).rotate_left(9);
+        x[14] ^= x[13].wrapping_add(x[12]).rotate_left(13);
+        x[15] ^= x[14].wrapping_add(x[13]).rotate_left(18);
+    }
+    for (word, mixed) in block.iter_mut().zip(x) {
+        *word = word.wrapping_add(mixed);
+    }
+}
+
+// BlockMix over 2 * r 64-byte blocks, using `scratch` of the same size.
+fn block_mix(b: &mut [u32], scratch: &mut [u32], r: usize) {
+    let mut x: [u32; 16] = b[(2 * r - 1) * 16..].try_into().unwrap();
+    for i in 0..2 * r {
+        for (word, input) in x.iter_mut().zip(&b[i * 16..(i + 1) * 16]) {
+            *word ^= input;
+        }
+        salsa20_8(&mut x);
+        // Even blocks go to the first half of the output, odd ones to the second.
+        let dest = (i / 2 + (i % 2) * r) * 16;
+        scratch[dest..dest + 16].copy_from_slice(&x);
+    }
+    b.copy_from_slice(scratch);
+}
+
+// ROMix over one `128 * r`-byte block with a table of `n` entries.
+fn ro_mix(block: &mut [u8], n: usize, r: usize) {
+    let words = 32 * r;
+    let mut x: Vec<u32> = block
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let mut scratch = vec![0u32; words];
+    let mut v = vec![0u32; words * n];
+
+    for i in 0..n {
+        v[i * words..(i + 1) * words].copy_from_slice(&x);
+        block_mix(&mut x, &mut scratch, r);
+    }
+    for _ in 0..n {
+        let j = x[(2 * r - 1) * 16] as usize & (n - 1);
+        for (word, stored) in x.iter_mut().zip(&v[j * words..(j + 1) * words]) {
+            *word ^= stored;
+        }
+        block_mix(&mut x, &mut scratch, r);
+    }
+
+    for (chunk, word) in block.chunks_exact_mut(4).zip(x) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+/// scrypt key derivation filling `out`. `n` must be a power of two greater than one.
+pub fn scrypt(password: &[u8], salt: &[u8], n: usize, r: usize, p: usize, out: &mut [u8]) {
+    assert!(n > 1 && n.is_power_of_two(), "scrypt cost parameter must be a power of two");
+    let block_len = 128 * r;
+    let mut b = vec![0u8; p * block_len];
+    pbkdf2_hmac_sha256(password, salt, 1, &mut b);
+    for block in b.chunks_exact_mut(block_len) {
+        ro_mix(block, n, r);
+    }
+    pbkdf2_hmac_sha256(password, &b, 1, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hex;
+
+    // RFC 4231 test cases 2 and 6: a short key and one longer than a block.
+    #[test]
+    fn hmac_sha256_vectors() {
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?").to_vec(),
+            hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+        assert_eq!(
+            hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First").to_vec(),
+            hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+    }
+
+    // RFC 7914 section 11.
+    #[test]
+    fn pbkdf2_hmac_sha256_vectors() {
+        let vectors: [(&str, &str, u32, &str); 2] = [
+            (
+                "passwd",
+                "salt",
+                1,
+                "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+                 49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783",
+            ),
+            (
+                "Password",
+                "NaCl",
+                80_000,
+                "4ddcd8f60b98be21830cee5ef22701f9641a4418d04c0414aeff08876b34ab56\
+                 a1d425a1225833549adb841b51c9b3176a272bdebba1d078478f62b397f33c8d",
+            ),
+        ];
+        for (password, salt, iterations, expected) in vectors {
+            let mut out = [0u8; 64];
+            pbkdf2_hmac_sha256(password.as_bytes(), salt.as_bytes(), iterations, &mut out);
+            assert_eq!(out.to_vec(), hex(expected));
+        }
+    }
+
+    // RFC 7914 section 12, but for the last vector, which takes a GiB.
+    #[test]
+    fn scrypt_vectors() {
+        let vectors: [(&str, &str, usize, usize, usize, &str); 3] = [
+            (
+                "",
+                "",
+                16,
+                1,
+                1,
+                "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+                 fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906",
+            ),
+            (
+                "password",
+                "NaCl",
+                1024,
+                8,
+                16,
+                "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+                 2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640",
+            ),
+            (
+                "pleaseletmein",
+                "SodiumChloride",
+                16384,
+                8,
+                1,
+                "7023bdcb3afd7348461c06cd81fd38ebfda8fbba904f8e3ea9b543f6545da1f2\
+                 d5432955613f0fcf62d49705242a9af9e61e85dc0d651e40dfcf017b45575887",
+            ),
+        ];
+        for (password, salt, n, r, p, expected) in vectors {
+            let mut out = [0u8; 64];
+            scrypt(password.as_bytes(), salt.as_bytes(), n, r, p, &mut out);
+            assert_eq!(out.to_vec(), hex(expected), "n={} r={} p={}", n, r, p);
+        }
+    }
+}
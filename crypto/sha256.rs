@@ -0,0 +1,247 @@
+// SHA-256 (FIPS 180-4)
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256 hasher.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; 64],
+    buffered: usize,
+    length: u64,
+}
+
+impl Sha256 {
+    /// Creates a hasher in the initial state.
+    pub fn new() -> Self {
+        Sha256 {
+            state: H0,
+            buffer: [0; 64],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    /// Feeds more bytes into the hasher.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        if self.buffered > 0 {
+            let take = (64 - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+            if self.buffered < 64 {
+                return;
+            }
+            let block = self.buffer;
+            compress(&mut self.state, &block);
+            self.buffered = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            compress(&mut self.state, block.try_into().unwrap());
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Applies the padding and returns the 32-byte digest.
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.length.wrapping_mul(8);
+        let mut padding = [0u8; 72];
+        padding[0] = 0x80;
+        let pad_len = if self.buffered < 56 { 56 - self.buffered } else { 120 - self.buffered };
+        padding[pad_len..pad_len + 8].copy_from_slice(&bit_len.to_be_bytes());
+        let length = self.length;
+        self.update(&padding[..pad_len + 8]);
+        self.length = length;
+
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    /// Absorbs `rest`, the end of the message, into a `PaddedTail` to finish from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rest` and the bytes buffered since the last full block add up to
+    /// more than 119, which does not fit in two blocks with the padding.
+    pub fn padded_tail(&self, rest: &[u8]) -> PaddedTail {
+        let message_len = self.buffered + rest.len();
+        assert!(message_len <= 119, "message tail longer than two blocks with padding");
+        let mut blocks = [0u8; 128];
+        blocks[..self.buffered].copy_from_slice(&self.buffer[..self.buffered]);
+        blocks[self.buffered..message_len].copy_from_slice(rest);
+        blocks[message_len] = 0x80;
+        let blocks_len = if message_len < 56 { 64 } else { 128 };
+        let bit_len = self.length.wrapping_add(rest.len() as u64).wrapping_mul(8);
+        blocks[blocks_len - 8..blocks_len].copy_from_slice(&bit_len.to_be_bytes());
+        PaddedTail {
+            state: self.state,
+            blocks,
+            blocks_len,
+            message_len,
+        }
+    }
+}
+
+/// The last one or two blocks of a message, already padded, and the hasher state
+/// before them. Message bytes in them can be changed in place and the digest taken
+/// again, without buffering, padding or copying a hasher; see `Sha256::padded_tail`.
+#[derive(Clone, Debug)]
+pub struct PaddedTail {
+    state: [u32; 8],
+    blocks: [u8; 128],
+    blocks_len: usize,
+    message_len: usize,
+}
+
+impl PaddedTail {
+    /// The last `len` bytes of the message.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is more than the message bytes in the tail.
+    pub fn tail_mut(&mut self, len: usize) -> &mut [u8] {
+        &mut self.blocks[self.message_len - len..self.message_len]
+    }
+
+    /// Returns the 32-byte digest of the message as it currently ends.
+    pub fn finalize(&self) -> [u8; 32] {
+        let mut state = self.state;
+        for block in self.blocks[..self.blocks_len].chunks_exact(64) {
+            compress(&mut state, block.try_into().unwrap());
+        }
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Single SHA-256 of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Double SHA-256 (`SHA-256(SHA-256(data))`), as used for block hashes.
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    sha256(&sha256(data))
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0u32; 64];
+    for (i, chunk) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(add);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hex;
+
+    // FIPS 180 example messages, plus the empty one.
+    #[test]
+    fn fips_180_vectors() {
+        let long = "a".repeat(1_000_000);
+        let vectors: [(&[u8], &str); 4] = [
+            (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (long.as_bytes(), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"),
+        ];
+        for (message, digest) in vectors {
+            assert_eq!(sha256(message).to_vec(), hex(digest));
+            let mut hasher = Sha256::new();
+            for chunk in message.chunks(37) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize().to_vec(), hex(digest));
+        }
+    }
+
+    #[test]
+    fn padded_tail_matches_the_hasher_after_changes() {
+        let message: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        for absorbed in [0, 30, 64, 80] {
+            for len in absorbed..=absorbed + 119 - absorbed % 64 {
+                let mut hasher = Sha256::new();
+                hasher.update(&message[..absorbed]);
+                let mut tail = hasher.padded_tail(&message[absorbed..len]);
+                assert_eq!(tail.finalize(), sha256(&message[..len]));
+
+                let mut changed = message[..len].to_vec();
+                let changes = (len - absorbed).min(4);
+                for byte in &mut changed[len - changes..] {
+                    *byte ^= 0xff;
+                }
+                tail.tail_mut(changes).copy_from_slice(&changed[len - changes..]);
+                assert_eq!(tail.finalize(), sha256(&changed));
+            }
+        }
+    }
+}
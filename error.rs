@@ -0,0 +1,190 @@
+// Errors returned by chain operations and validation
+
+use crate::hash::Hash256;
+use crate::pow::PowKind;
+use crate::transaction::OutPoint;
+use std::fmt;
+
+/// Errors returned by chain operations. Validation variants carry the height of
+/// the offending block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    /// Chain or proof-of-work parameters are unusable, e.g. a target that does not decode.
+    InvalidParams(&'static str),
+    /// The system clock reports a time before the Unix epoch.
+    ClockError,
+    /// Every nonce was tried without finding a hash that meets the target.
+    NonceSpaceExhausted { height: u32 },
+    /// Mining was cancelled through its `CancelHandle` before a hash met the target.
+    MiningCancelled { height: u32 },
+    /// Mining reached its deadline before a hash met the target.
+    MiningDeadlineExceeded { height: u32 },
+    /// A transaction's signature does not match its sender and contents.
+    InvalidSignature(Hash256),
+    /// A transaction outside genesis spends nothing, so it could only create value.
+    NoInputs(Hash256),
+    /// A transaction spends an output that does not exist or is already spent.
+    MissingInput { tx_id: Hash256, input: OutPoint },
+    /// A transaction spends an output paid to someone other than its sender.
+    InputNotOwned { tx_id: Hash256, input: OutPoint },
+    /// A transaction spends a coinbase output before it has matured.
+    ImmatureCoinbase { tx_id: Hash256, input: OutPoint, spendable_from: u32 },
+    /// A transaction's inputs do not add up to its outputs plus its fee.
+    UnbalancedTransaction { tx_id: Hash256, input_total: u128, output_total: u128 },
+    /// A transaction on an account chain names outputs to spend.
+    UnexpectedInputs(Hash256),
+    /// A transaction's nonce is not its sender's next nonce.
+    BadNonce { tx_id: Hash256, expected: u64, actual: u64 },
+    /// A transaction pays out more than its sender's balance.
+    InsufficientBalance { tx_id: Hash256, balance: u64, required: u128 },
+    /// A coinbase transaction is missing or malformed.
+    BadCoinbase { height: u32, reason: &'static str },
+    /// A coinbase-shaped transaction appears anywhere but first in a block.
+    UnexpectedCoinbase(Hash256),
+    /// A coinbase claims more than the block's subsidy plus its fees.
+    ExcessiveCoinbase { height: u32, claimed: u128, allowed: u128 },
+    /// A block issues coins beyond the chain's maximum supply.
+    SupplyExceeded { height: u32, supply: u128, max_supply: u64 },
+    /// The transaction is already in the mempool.
+    DuplicateTransaction(Hash256),
+    /// The transaction spends what a mempool transaction already spends.
+    MempoolConflict { tx_id: Hash256, existing: Hash256 },
+    /// A replacement does not pay enough more than the transactions it would replace.
+    ReplacementFeeTooLow { tx_id: Hash256, fee: u64, required: u128 },
+    /// The mempool is full of transactions paying at least the same fee rate.
+    MempoolFull(Hash256),
+    /// A block is larger than the chain allows.
+    BlockTooLarge { height: u32, size: usize, max_size: usize },
+    /// No block in the chain contains a transaction with this id.
+    UnknownTransaction(Hash256),
+    /// The chain has no blocks, not even genesis.
+    EmptyChain,
+    /// The first block is not the genesis block these parameters produce.
+    BadGenesis,
+    /// The chain was built with a different proof-of-work algorithm, or different
+    /// cost parameters, than its parameters name.
+    PowAlgorithmMismatch { expected: PowKind, actual: PowKind },
+    /// A block's height does not follow its parent's.
+    NonSequentialHeight { expected: u32, actual: u32 },
+    /// A block does not link to the hash of its parent.
+    BadPrevHash { height: u32, expected: Hash256, actual: Hash256 },
+    /// A block's Merkle root does not commit to its transactions.
+    BadMerkleRoot { height: u32 },
+    /// A block's state root does not match the state after applying it.
+    BadStateRoot { height: u32, expected: Hash256, actual: Hash256 },
+    /// A block's hash does not meet the target it claims.
+    BadPow { height: u32 },
+    /// A block's bits do not decode to a valid target; see `Target::from_compact`.
+    BadBits { height: u32, bits: u32 },
+    /// A block claims a different target than retargeting yields at its height.
+    BadDifficulty { height: u32, expected: u32, actual: u32 },
+    /// A block's timestamp is not later than the median time past of the blocks before it.
+    TimestampTooOld { height: u32, timestamp: u64, median_time_past: u64 },
+    /// A block's timestamp is further ahead of the clock than the chain allows.
+    TimestampTooFarAhead { height: u32, timestamp: u64, max: u64 },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::InvalidParams(reason) => write!(f, "invalid chain parameters: {}", reason),
+            ChainError::ClockError => write!(f, "system clock is set before the Unix epoch"),
+            ChainError::NonceSpaceExhausted { height } => {
+                write!(f, "no nonce yields a valid hash for block {}", height)
+            }
+            ChainError::MiningCancelled { height } => write!(f, "mining block {} was cancelled", height),
+            ChainError::MiningDeadlineExceeded { height } => {
+                write!(f, "mining block {} ran past its deadline", height)
+            }
+            ChainError::InvalidSignature(id) => write!(f, "transaction {} has an invalid signature", id),
+            ChainError::NoInputs(id) => write!(f, "transaction {} spends no outputs", id),
+            ChainError::MissingInput { tx_id, input } => {
+                write!(f, "transaction {} spends {}, which is missing or already spent", tx_id, input)
+            }
+            ChainError::InputNotOwned { tx_id, input } => {
+                write!(f, "transaction {} spends {}, which is not paid to its sender", tx_id, input)
+            }
+            ChainError::ImmatureCoinbase { tx_id, input, spendable_from } => write!(
+                f,
+                "transaction {} spends coinbase output {}, which is not spendable before block {}",
+                tx_id, input, spendable_from
+            ),
+            ChainError::UnbalancedTransaction { tx_id, input_total, output_total } => write!(
+                f,
+                "transaction {} spends {} but pays out {} including its fee",
+                tx_id, input_total, output_total
+            ),
+            ChainError::UnexpectedInputs(id) => {
+                write!(f, "transaction {} spends outputs, but the chain uses accounts", id)
+            }
+            ChainError::BadNonce { tx_id, expected, actual } => {
+                write!(f, "transaction {} has nonce {}, expected {}", tx_id, actual, expected)
+            }
+            ChainError::InsufficientBalance { tx_id, balance, required } => write!(
+                f,
+                "transaction {} needs {} including its fee, but its sender holds {}",
+                tx_id, required, balance
+            ),
+            ChainError::BadCoinbase { height, reason } => write!(f, "block {} has a bad coinbase: {}", height, reason),
+            ChainError::UnexpectedCoinbase(id) => {
+                write!(f, "transaction {} is a coinbase but is not first in its block", id)
+            }
+            ChainError::ExcessiveCoinbase { height, claimed, allowed } => {
+                write!(f, "block {} coinbase claims {}, at most {} is allowed", height, claimed, allowed)
+            }
+            ChainError::SupplyExceeded { height, supply, max_supply } => {
+                write!(f, "block {} brings the supply to {}, above the maximum {}", height, supply, max_supply)
+            }
+            ChainError::DuplicateTransaction(id) => write!(f, "transaction {} is already in the mempool", id),
+            ChainError::MempoolConflict { tx_id, existing } => {
+                write!(f, "transaction {} conflicts with mempool transaction {}", tx_id, existing)
+            }
+            ChainError::ReplacementFeeTooLow { tx_id, fee, required } => write!(
+                f,
+                "transaction {} pays a fee of {}, but replacing what it conflicts with takes at least {}",
+                tx_id, fee, required
+            ),
+            ChainError::MempoolFull(id) => {
+                write!(f, "mempool is full and transaction {} pays too low a fee rate", id)
+            }
+            ChainError::BlockTooLarge { height, size, max_size } => {
+                write!(f, "block {} is {} bytes, more than the maximum {}", height, size, max_size)
+            }
+            ChainError::UnknownTransaction(id) => write!(f, "no block contains transaction {}", id),
+            ChainError::EmptyChain => write!(f, "chain has no genesis block"),
+            ChainError::BadGenesis => write!(f, "genesis block does not match the chain parameters"),
+            ChainError::PowAlgorithmMismatch { expected, actual } => {
+                write!(f, "chain parameters require {} proof of work, got {}", expected, actual)
+            }
+            ChainError::NonSequentialHeight { expected, actual } => {
+                write!(f, "expected block height {}, got {}", expected, actual)
+            }
+            ChainError::BadPrevHash { height, expected, actual } => {
+                write!(f, "block {} links to {}, expected parent {}", height, actual, expected)
+            }
+            ChainError::BadMerkleRoot { height } => {
+                write!(f, "block {} merkle root does not match its transactions", height)
+            }
+            ChainError::BadStateRoot { height, expected, actual } => {
+                write!(f, "block {} commits to state {}, expected {}", height, actual, expected)
+            }
+            ChainError::BadPow { height } => write!(f, "block {} hash does not meet its target", height),
+            ChainError::BadBits { height, bits } => {
+                write!(f, "block {} bits {:#010x} are not a valid compact target", height, bits)
+            }
+            ChainError::BadDifficulty { height, expected, actual } => {
+                write!(f, "block {} claims target {:#010x}, expected {:#010x}", height, actual, expected)
+            }
+            ChainError::TimestampTooOld { height, timestamp, median_time_past } => write!(
+                f,
+                "block {} timestamp {} is not after the median time past {}",
+                height, timestamp, median_time_past
+            ),
+            ChainError::TimestampTooFarAhead { height, timestamp, max } => {
+                write!(f, "block {} timestamp {} is ahead of the latest allowed {}", height, timestamp, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
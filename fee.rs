@@ -0,0 +1,154 @@
+// Fee rates and fee estimation from recent blocks and the mempool
+
+use crate::block::{Block, HEADER_LEN};
+use crate::mempool::{Mempool, MempoolEntry};
+use crate::params::ChainParams;
+use crate::transaction::{Address, Transaction, TxOutput};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Number of most recent blocks whose fee rates feed into an estimate.
+pub const HISTORY_BLOCKS: usize = 24;
+
+/// A fee per 1000 bytes of encoded transaction; see `Transaction::size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct FeeRate(pub u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    /// The rate paid by a transaction of `size` bytes paying `fee`, rounded down.
+    pub fn of(fee: u64, size: usize) -> Self {
+        let rate = fee as u128 * 1000 / size.max(1) as u128;
+        FeeRate(rate.min(u64::MAX as u128) as u64)
+    }
+
+    /// The fee a transaction of `size` bytes must pay to reach this rate, rounded up.
+    pub fn fee_for(&self, size: usize) -> u64 {
+        let fee = (self.0 as u128 * size as u128).div_ceil(1000);
+        fee.min(u64::MAX as u128) as u64
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} per kB", self.0)
+    }
+}
+
+/// Estimates the fee rate a transaction needs to be mined within `target_blocks`
+/// blocks on top of `chain`, treating a target of 0 as 1.
+///
+/// Two signals are combined and the higher one wins:
+/// - History: the lowest rate mined in each of the last `HISTORY_BLOCKS` blocks,
+///   counting blocks less than 95% full as free. Sorted from highest to lowest,
+///   the `target_blocks`-th one is what beat the competition in enough of them.
+/// - Backlog: the mempool, filling `target_blocks` blocks. Like `build_template`,
+///   entries are ranked by the rate of their ancestor package, and a package only
+///   takes up the room of members not counted yet. A transaction has to outbid
+///   the first package that does not fit.
+pub fn estimate(params: &ChainParams, chain: &[Block], mempool: &Mempool, target_blocks: u32) -> FeeRate {
+    let target_blocks = target_blocks.max(1) as usize;
+
+    let mut history: Vec<FeeRate> = chain
+        .iter()
+        .skip(1)
+        .rev()
+        .take(HISTORY_BLOCKS)
+        .map(|block| {
+            if block.size() * 20 < params.rules_at(block.height()).max_block_size * 19 {
+                return FeeRate::ZERO;
+            }
+            block
+                .transactions
+                .iter()
+                .skip(1)
+                .map(|transaction| FeeRate::of(transaction.fee, transaction.size()))
+                .min()
+                .unwrap_or(FeeRate::ZERO)
+        })
+        .collect();
+    history.sort_by_key(|&rate| Reverse(rate));
+    let from_history = history
+        .get(target_blocks - 1)
+        .or(history.last())
+        .copied()
+        .unwrap_or(FeeRate::ZERO);
+
+    let coinbase = Transaction::coinbase(0, vec![TxOutput { recipient: Address::default(), amount: 0 }]);
+    let max_block_size = params.rules_at(chain.len() as u32).max_block_size;
+    let block_space = max_block_size.saturating_sub(HEADER_LEN + coinbase.size());
+    let mut backlog: Vec<(FeeRate, Vec<&MempoolEntry>)> = mempool
+        .entries()
+        .map(|entry| {
+            let package = mempool.ancestor_package(&entry.id);
+            let fee = package.iter().fold(0u64, |fee, member| fee.saturating_add(member.transaction.fee));
+            let size = package.iter().map(|member| member.size).sum();
+            (FeeRate::of(fee, size), package)
+        })
+        .collect();
+    backlog.sort_by_key(|(rate, _)| Reverse(*rate));
+    let mut counted = HashSet::new();
+    let mut remaining = block_space.saturating_mul(target_blocks);
+    let mut from_backlog = FeeRate::ZERO;
+    for (rate, package) in backlog {
+        let size: usize = package
+            .iter()
+            .filter(|member| counted.insert(member.id))
+            .map(|member| member.size)
+            .sum();
+        if size > remaining {
+            from_backlog = FeeRate(rate.0.saturating_add(1));
+            break;
+        }
+        remaining -= size;
+    }
+
+    from_history.max(from_backlog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::mempool::MempoolConfig;
+    use crate::pow::PowKind;
+    use crate::state::ChainState;
+    use crate::transaction::OutPoint;
+
+    #[test]
+    fn backlog_is_ranked_by_ancestor_package_rate() {
+        let keys: Vec<SigningKey> = (1..=3).map(|seed| SigningKey::from_seed(&[seed; 32])).collect();
+        let pay = |key: &SigningKey, amount| TxOutput { recipient: Address::of(key), amount };
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.genesis_outputs = keys.iter().map(|key| pay(key, 100)).collect();
+        let genesis = Block::genesis(&params);
+        let coinbase = &genesis.transactions[0];
+        let mut state = ChainState::new(&params);
+        state.connect(0, &genesis.transactions).unwrap();
+        let coin = |tx: &Transaction, index| vec![OutPoint { tx_id: tx.id(), index }];
+
+        // A low-fee parent with a high-fee child, and two plain transactions in between.
+        let parent = Transaction::new_signed(&keys[0], coin(coinbase, 0), vec![pay(&keys[0], 99)], 1, 0);
+        let child = Transaction::new_signed(&keys[0], coin(&parent, 0), vec![pay(&keys[0], 69)], 30, 0);
+        let other = Transaction::new_signed(&keys[1], coin(coinbase, 1), vec![pay(&keys[1], 90)], 10, 0);
+        let low = Transaction::new_signed(&keys[2], coin(coinbase, 2), vec![pay(&keys[2], 98)], 2, 0);
+        let size = parent.size();
+        assert!([&child, &other, &low].iter().all(|tx| tx.size() == size));
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        for tx in [&parent, &child, &other, &low] {
+            mempool.insert(tx.clone(), &state, 1, 0).unwrap();
+        }
+
+        // Room for two transactions per block.
+        let coinbase_size = Transaction::coinbase(0, vec![TxOutput { recipient: Address::default(), amount: 0 }]).size();
+        params.rules.max_block_size = HEADER_LEN + coinbase_size + 2 * size;
+        let chain = [genesis];
+        // The child's package takes the first block, so `other` is what has to be beaten,
+        // not `low`, which ranking by individual rate would have put second.
+        assert_eq!(estimate(&params, &chain, &mempool, 1), FeeRate(FeeRate::of(10, size).0 + 1));
+        assert_eq!(estimate(&params, &chain, &mempool, 2), FeeRate::ZERO);
+        assert_eq!(estimate(&params, &chain, &Mempool::default(), 1), FeeRate::ZERO);
+    }
+}
@@ -0,0 +1,118 @@
+// Fixed-width 256-bit hash
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A 32-byte digest, displayed and parsed as 64 lowercase hex characters.
+///
+/// Ordering compares the bytes big-endian, which matches the order of the hex strings.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Hash256(pub [u8; 32]);
+
+impl Hash256 {
+    /// The all-zero hash, used as the previous hash of the genesis block.
+    pub const ZERO: Hash256 = Hash256([0; 32]);
+
+    /// Returns the raw digest bytes.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Hash256 {
+    fn from(bytes: [u8; 32]) -> Self {
+        Hash256(bytes)
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash256({})", self)
+    }
+}
+
+/// Error returned when parsing a `Hash256` from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseHashError {
+    /// The string was not exactly 64 characters long.
+    InvalidLength(usize),
+    /// The string contained a character that is not a hex digit.
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for ParseHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHashError::InvalidLength(len) => write!(f, "expected 64 hex characters, got {}", len),
+            ParseHashError::InvalidCharacter(c) => write!(f, "invalid hex character {:?}", c),
+        }
+    }
+}
+
+impl std::error::Error for ParseHashError {}
+
+impl FromStr for Hash256 {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(ParseHashError::InvalidCharacter(c));
+        }
+        if s.len() != 64 {
+            return Err(ParseHashError::InvalidLength(s.len()));
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+            let pair = std::str::from_utf8(pair).unwrap();
+            *byte = u8::from_str_radix(pair, 16).unwrap();
+        }
+        Ok(Hash256(bytes))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Hash256 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Hash256 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        let hash = Hash256(std::array::from_fn(|i| i as u8 * 7));
+        assert_eq!(hash.to_string().parse::<Hash256>(), Ok(hash));
+        assert_eq!("ab".parse::<Hash256>(), Err(ParseHashError::InvalidLength(2)));
+        assert_eq!("zz".parse::<Hash256>(), Err(ParseHashError::InvalidCharacter('z')));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_uses_the_hex_string() {
+        let hash = Hash256(std::array::from_fn(|i| i as u8 * 7));
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", hash));
+        assert_eq!(serde_json::from_str::<Hash256>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<Hash256>("\"ab\"").is_err());
+    }
+}
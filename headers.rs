@@ -0,0 +1,230 @@
+// Header-only chains, synced and checked without block bodies, for light clients
+
+use crate::block::{self, current_timestamp, Block, BlockHeader};
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::merkle::{self, InclusionProof};
+use crate::params::ChainParams;
+use crate::pow::{self, PowAlgorithm};
+use crate::retarget;
+use crate::target::Target;
+use crate::uint::U256;
+use crate::versionbits::{DeploymentState, DeploymentStates};
+
+/// A chain of validated headers from genesis.
+///
+/// Every header links to its parent, meets the target retargeting expects and
+/// carries the proof of work for it. Bodies are never seen, so the ledger state
+/// and the transactions are not checked; `verify_inclusion` still shows a
+/// transaction was mined in one of the blocks.
+#[derive(Debug)]
+pub struct HeaderChain<P: PowAlgorithm> {
+    headers: Vec<BlockHeader>,
+    /// `hashes[i]` is the hash of `headers[i]`, so that each header is hashed once.
+    hashes: Vec<Hash256>,
+    /// The deployment states `headers` have reached, updated as headers are added.
+    deployments: DeploymentStates,
+    pub params: ChainParams,
+    pub pow: P,
+}
+
+impl<P: PowAlgorithm> HeaderChain<P> {
+    /// Starts a header chain at the genesis header `params` produce.
+    pub fn new(pow: P, params: ChainParams) -> Result<Self, ChainError> {
+        pow::check_schedule(&pow, &params)?;
+        let genesis = Block::genesis(&params).header;
+        let hash = genesis.calculate_hash(&pow::at_height(&pow, &params, 0)?);
+        let mut deployments = DeploymentStates::default();
+        deployments.update(&params, &[genesis]);
+        Ok(HeaderChain {
+            headers: vec![genesis],
+            hashes: vec![hash],
+            deployments,
+            params,
+            pow,
+        })
+    }
+
+    /// Rebuilds a header chain from headers received from a peer, genesis first,
+    /// rejecting it unless every header validates.
+    pub fn from_headers(pow: P, params: ChainParams, headers: Vec<BlockHeader>) -> Result<Self, ChainError> {
+        let mut chain = Self::new(pow, params)?;
+        let mut headers = headers.into_iter();
+        let genesis = headers.next().ok_or(ChainError::EmptyChain)?;
+        if genesis != chain.headers[0] {
+            return Err(ChainError::BadGenesis);
+        }
+        for header in headers {
+            chain.add_header(header)?;
+        }
+        Ok(chain)
+    }
+
+    /// Appends `header` if it extends the tip: see `BlockHeader::validate_against`,
+    /// hashing with the algorithm scheduled at its height, `retarget::check_bits`
+    /// and `block::check_timestamp` against the clock.
+    /// Returns its hash.
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<Hash256, ChainError> {
+        let pow = pow::at_height(&self.pow, &self.params, self.headers.len() as u32)?;
+        let hash = header.validate_against(self.tip(), &self.tip_hash(), &pow)?;
+        retarget::check_bits(&self.params, &self.headers, &header)?;
+        block::check_timestamp(&self.params, &self.headers, &header, current_timestamp()?)?;
+        self.headers.push(header);
+        self.hashes.push(hash);
+        self.deployments.update(&self.params, &self.headers);
+        Ok(hash)
+    }
+
+    /// Every header, genesis first.
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    /// The header at `height`, if the chain is that long.
+    pub fn header(&self, height: u32) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    /// The hash of the header at `height`, if the chain is that long.
+    pub fn hash(&self, height: u32) -> Option<Hash256> {
+        self.hashes.get(height as usize).copied()
+    }
+
+    /// The last header.
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().expect("a header chain starts at genesis")
+    }
+
+    /// The hash of the last header, which the next one must link to.
+    pub fn tip_hash(&self) -> Hash256 {
+        *self.hashes.last().expect("a header chain starts at genesis")
+    }
+
+    /// Returns the compact target the next header must meet under the retargeting rule.
+    pub fn next_bits(&self) -> Result<u32, ChainError> {
+        retarget::next_bits(&self.params, &self.headers)
+    }
+
+    /// State of the deployment called `name` for the next header, or `None` if the
+    /// parameters define no such deployment. Signalling is in the headers alone,
+    /// so a light client tracks deployments just as a full node does.
+    pub fn deployment_state(&self, name: &str) -> Option<DeploymentState> {
+        let index = self.params.deployments.iter().position(|deployment| deployment.name == name)?;
+        Some(self.deployments.get(index))
+    }
+
+    /// Sums the expected work of every header after genesis; see `total_work`.
+    pub fn total_work(&self) -> U256 {
+        total_work(&self.headers)
+    }
+
+    /// Checks that `proof` shows its transaction is committed to by the header
+    /// at the proof's height in this chain.
+    pub fn verify_inclusion(&self, proof: &InclusionProof) -> bool {
+        self.header(proof.header.height)
+            .is_some_and(|header| merkle::verify_inclusion(proof, header))
+    }
+}
+
+/// Sums the expected work of every block or header after genesis, for picking
+/// the heaviest fork.
+///
+/// Saturates at `U256::MAX` rather than overflowing.
+pub fn total_work<H: AsRef<BlockHeader>>(chain: &[H]) -> U256 {
+    chain
+        .iter()
+        .skip(1)
+        .filter_map(|header| Target::from_compact(header.as_ref().bits))
+        .fold(U256::ZERO, |work, target| work.checked_add(target.work()).unwrap_or(U256::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use crate::miner::{self, MinerConfig};
+    use crate::pow::Sha256d;
+    use crate::transaction::Address;
+
+    /// A full node's chain of three blocks after genesis, and its headers.
+    fn mined_chain() -> (Blockchain<Sha256d>, Vec<BlockHeader>) {
+        let mut blockchain = Blockchain::new(Sha256d, 0x207f_ffff).unwrap();
+        for _ in 0..3 {
+            blockchain.add_block(Address::default(), vec![]).unwrap();
+        }
+        let headers = blockchain.headers().copied().collect();
+        (blockchain, headers)
+    }
+
+    /// Re-mines the header at `height` after `change`, keeping its proof of work valid.
+    fn tampered(headers: &[BlockHeader], height: usize, change: impl FnOnce(&mut BlockHeader)) -> Vec<BlockHeader> {
+        let mut headers = headers.to_vec();
+        change(&mut headers[height]);
+        headers[height] = miner::mine(&Sha256d, headers[height], &MinerConfig::default()).unwrap().0;
+        headers
+    }
+
+    #[test]
+    fn from_headers_rejects_headers_that_do_not_extend_the_chain() {
+        let (blockchain, headers) = mined_chain();
+        let params = blockchain.params.clone();
+        let sync = |headers: Vec<BlockHeader>| HeaderChain::from_headers(Sha256d, params.clone(), headers).err();
+        assert_eq!(sync(headers.clone()), None);
+
+        let mut genesis = headers.clone();
+        genesis[0].timestamp += 1;
+        assert_eq!(sync(genesis), Some(ChainError::BadGenesis));
+        assert_eq!(sync(vec![]), Some(ChainError::EmptyChain));
+
+        let unlinked = tampered(&headers, 2, |header| header.previous_hash = Hash256::ZERO);
+        assert_eq!(
+            sync(unlinked),
+            Some(ChainError::BadPrevHash {
+                height: 2,
+                expected: blockchain.chain[1].calculate_hash(&Sha256d),
+                actual: Hash256::ZERO,
+            })
+        );
+
+        let harder = tampered(&headers, 2, |header| header.bits = 0x2000_ffff);
+        assert_eq!(
+            sync(harder),
+            Some(ChainError::BadDifficulty {
+                height: 2,
+                expected: 0x207f_ffff,
+                actual: 0x2000_ffff,
+            })
+        );
+
+        let mut unmined = headers.clone();
+        unmined[2].nonce = (0..)
+            .find(|&nonce| {
+                let header = BlockHeader { nonce, ..headers[2] };
+                !block::is_valid_hash(&header.calculate_hash(&Sha256d), header.bits)
+            })
+            .unwrap();
+        assert_eq!(sync(unmined), Some(ChainError::BadPow { height: 2 }));
+
+        // Stamped at genesis time, before the median of genesis and block 1.
+        let early = tampered(&headers, 2, |header| header.timestamp = headers[0].timestamp);
+        assert!(matches!(sync(early), Some(ChainError::TimestampTooOld { height: 2, .. })));
+    }
+
+    #[test]
+    fn inclusion_is_only_verified_against_headers_in_the_chain() {
+        let (blockchain, headers) = mined_chain();
+        let proof = blockchain.prove_inclusion(&blockchain.chain[2].transactions[0].id()).unwrap();
+        let light = HeaderChain::from_headers(Sha256d, blockchain.params.clone(), headers.clone()).unwrap();
+        assert!(light.verify_inclusion(&proof));
+
+        // A client synced only up to block 1 has no header to check block 2 against.
+        let behind = HeaderChain::from_headers(Sha256d, blockchain.params.clone(), headers[..2].to_vec()).unwrap();
+        assert!(!behind.verify_inclusion(&proof));
+        let mut past_tip = proof.clone();
+        past_tip.header.height = 10;
+        assert!(!light.verify_inclusion(&past_tip));
+        let mut other_block = proof;
+        other_block.header = headers[3];
+        assert!(!light.verify_inclusion(&other_block));
+    }
+}
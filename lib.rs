@@ -0,0 +1,36 @@
+// Proof-of-work blockchain: blocks, transactions, ledger state, mempool and mining
+
+pub mod account;
+pub mod bench;
+pub mod block;
+pub mod blockchain;
+pub mod crypto;
+pub mod error;
+pub mod fee;
+pub mod hash;
+pub mod headers;
+pub mod mempool;
+pub mod merkle;
+pub mod miner;
+pub mod params;
+pub mod pow;
+pub mod retarget;
+pub mod smt;
+pub mod state;
+pub mod target;
+pub mod transaction;
+pub mod uint;
+pub mod utxo;
+pub mod versionbits;
+
+pub use block::{Block, BlockHeader};
+pub use blockchain::Blockchain;
+pub use error::ChainError;
+pub use hash::Hash256;
+pub use headers::HeaderChain;
+pub use mempool::{Mempool, MempoolConfig};
+pub use miner::{CancelHandle, MinerConfig};
+pub use params::ChainParams;
+pub use pow::{AnyPow, Blake3, PowAlgorithm, PowKind, Scrypt, Sha256d};
+pub use state::{ChainState, StateModel};
+pub use transaction::{Address, OutPoint, Transaction, TxOutput};
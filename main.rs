@@ -1,128 +1,207 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::time::{SystemTime, UNIX_EPOCH};
-
-// Block structure
-#[derive(Debug)]
-struct Block {
-    index: u32,
-    timestamp: u64,
-    data: String,
-    previous_hash: String,
-    hash: String,
-    nonce: u64,
-}
-
-// Blockchain structure
-#[derive(Debug)]
-struct Blockchain {
-    chain: Vec<Block>,
-    pending_transactions: Vec<String>,
-    difficulty: usize,
-}
-
-impl Blockchain {
-    /// Creates a new Blockchain with a genesis block.
-    fn new(difficulty: usize) -> Self {
-        let genesis_block = Block {
-            index: 0,
-            timestamp: 0,
-            data: "Genesis Block".to_string(),
-            previous_hash: "0".to_string(),
-            hash: "0".to_string(),
-            nonce: 0,
-        };
-        Blockchain {
-            chain: vec![genesis_block],
-            pending_transactions: vec![],
-            difficulty,
+// Demo chain and benchmark runner for the anpow library
+
+use anpow::bench::{self, BenchConfig};
+use anpow::crypto::ed25519::SigningKey;
+use anpow::fee::FeeRate;
+use anpow::retarget::Retarget;
+use anpow::versionbits::Deployment;
+use anpow::{
+    Address, AnyPow, Blake3, BlockHeader, Blockchain, ChainError, ChainParams, HeaderChain, OutPoint, PowAlgorithm,
+    PowKind, Scrypt, Sha256d, Transaction, TxOutput,
+};
+use std::process::ExitCode;
+use std::sync::mpsc;
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let bench = args.first().is_some_and(|arg| arg == "bench");
+    if bench {
+        args.remove(0);
+        if args.is_empty() {
+            args = vec!["sha256d".to_string(), "blake3".to_string(), "scrypt".to_string()];
+        }
+    } else {
+        args.truncate(1);
+        if args.is_empty() {
+            args.push("sha256d".to_string());
         }
     }
-
-    /// Adds a block to the blockchain with the given data.
-    fn add_block(&mut self, data: String) {
-        let previous_block = self.chain.last().expect("Blockchain should have at least one block.");
-        let timestamp = current_timestamp();
-
-        let (nonce, hash) = self.mine_block(previous_block.hash.clone(), timestamp, data.clone());
-
-        let block = Block {
-            index: self.chain.len() as u32,
-            timestamp,
-            data,
-            previous_hash: previous_block.hash.clone(),
-            hash,
-            nonce,
-        };
-        self.chain.push(block);
-    }
-
-    /// Mines a block by finding a nonce that satisfies the difficulty condition.
-    fn mine_block(&self, previous_hash: String, timestamp: u64, data: String) -> (u64, String) {
-        let mut nonce = 0;
-        loop {
-            let hash = self.calculate_hash(&previous_hash, timestamp, &data, nonce);
-            if self.is_valid_hash(&hash) {
-                return (nonce, hash);
+    for algorithm in args {
+        let kind = match algorithm.as_str() {
+            "sha256d" => PowKind::Sha256d,
+            "blake3" => PowKind::Blake3,
+            "scrypt" => Scrypt::default().kind(),
+            other => {
+                eprintln!("Unknown proof-of-work algorithm: {} (expected sha256d, blake3 or scrypt)", other);
+                return ExitCode::from(2);
             }
-            nonce += 1;
+        };
+        let result = if bench { run_bench(kind) } else { AnyPow::new(kind).and_then(run) };
+        if let Err(err) = result {
+            eprintln!("Error: {}", err);
+            return ExitCode::FAILURE;
         }
     }
+    ExitCode::SUCCESS
+}
 
-    /// Calculates the hash of the block using previous hash, timestamp, data, and nonce.
-    fn calculate_hash(&self, previous_hash: &str, timestamp: u64, data: &str, nonce: u64) -> String {
-        let mut hasher = DefaultHasher::new();
-        previous_hash.hash(&mut hasher);
-        timestamp.hash(&mut hasher);
-        data.hash(&mut hasher);
-        nonce.hash(&mut hasher);
-        format!("{:x}", hasher.finish())
+/// Runs the default benchmark suite for the algorithm, printing one JSON object per result.
+/// It hashes with the algorithm's own type, so the numbers leave out `AnyPow`'s dispatch.
+fn run_bench(kind: PowKind) -> Result<(), ChainError> {
+    match kind {
+        PowKind::Sha256d => bench_with(Sha256d),
+        PowKind::Blake3 => bench_with(Blake3),
+        PowKind::Scrypt { n, r, p } => bench_with(Scrypt::new(n, r, p)?),
     }
+}
 
-    /// Validates that the hash meets the difficulty level by checking the number of leading zeros.
-    fn is_valid_hash(&self, hash: &str) -> bool {
-        hash.starts_with(&"0".repeat(self.difficulty))
+fn bench_with<P: PowAlgorithm>(pow: P) -> Result<(), ChainError> {
+    for measurement in bench::run(&pow, &BenchConfig::for_algorithm(pow.kind()))? {
+        println!("{}", measurement);
     }
+    Ok(())
+}
 
-    /// Adds a transaction to the pending transactions list.
-    fn add_transaction(&mut self, transaction: String) {
-        self.pending_transactions.push(transaction);
+/// Mines a few demo blocks with the given proof-of-work algorithm and prints the chain.
+fn run<P: PowAlgorithm>(pow: P) -> Result<(), ChainError> {
+    let alice = SigningKey::from_seed(&[1; 32]);
+    let bob = SigningKey::from_seed(&[2; 32]);
+    let miner = Address::of(&SigningKey::from_seed(&[3; 32]));
+
+    let mut params = ChainParams::new(pow.kind(), 0x1f00ffff); // About four leading zero hex digits
+    params.genesis_outputs = vec![TxOutput {
+        recipient: Address::of(&alice),
+        amount: 100,
+    }];
+    // A soft fork that starts straight away, in windows short enough for the demo to reach.
+    params.deployments = vec![Deployment {
+        name: "demo",
+        bit: 0,
+        start_time: 0,
+        timeout: u64::MAX,
+    }];
+    params.signal_window = 2;
+    params.signal_threshold = 2;
+    // The demo's blocks come far faster than the spacing, which would soon make them hard to mine.
+    params.retarget = Retarget::Fixed;
+    let mut blockchain = Blockchain::with_params(pow, params)?;
+    blockchain.miner.threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let (progress, reports) = mpsc::channel();
+    blockchain.miner.progress = Some(progress);
+
+    // Spends `input` (worth `value`) from `from`, paying `amount` to `to` and the change back.
+    // The payments opt in to replace-by-fee, so a sender may bump the fee while it waits.
+    let pay = |from: &SigningKey, input: OutPoint, value: u64, to: &SigningKey, amount: u64, fee: u64| {
+        let outputs = vec![
+            TxOutput {
+                recipient: Address::of(to),
+                amount,
+            },
+            TxOutput {
+                recipient: Address::of(from),
+                amount: value - amount - fee,
+            },
+        ];
+        Transaction::new_replaceable(from, vec![input], outputs, fee, 0)
+    };
+
+    let (genesis_output, _) = blockchain.unspent_outputs(&Address::of(&alice))[0];
+    let transaction_1 = pay(&alice, genesis_output, 100, &bob, 50, 1);
+    let to_bob = OutPoint {
+        tx_id: transaction_1.id(),
+        index: 0,
+    };
+    let alice_change = OutPoint {
+        tx_id: transaction_1.id(),
+        index: 1,
+    };
+    let transaction_2 = pay(&bob, to_bob, 50, &alice, 20, 1);
+    blockchain.add_transaction(transaction_1)?;
+    blockchain.add_transaction(transaction_2.clone())?;
+    // Bob pays a higher fee for the same payment, which takes the place of the first one.
+    let replaced = transaction_2.id();
+    let transaction_2 = pay(&bob, to_bob, 50, &alice, 20, 2);
+    blockchain.add_transaction(transaction_2.clone())?;
+    if let Some(bumped) = blockchain.mempool.get(&transaction_2.id()) {
+        println!(
+            "Mempool: {} transactions, {} bytes - Bumped payment pays {} - Replaced one still pending: {}",
+            blockchain.mempool.len(),
+            blockchain.mempool.bytes(),
+            FeeRate::of(bumped.transaction.fee, bumped.size),
+            blockchain.mempool.contains(&replaced)
+        );
     }
-
-    /// Mines a new block with all pending transactions.
-    fn mine_and_add_block(&mut self) {
-        if self.pending_transactions.is_empty() {
-            return;
-        }
-        let data = self.pending_transactions.join(";");
-        self.add_block(data);
-        self.pending_transactions.clear();
+    blockchain.mine_and_add_block(miner)?;
+
+    let transaction_3 = pay(&alice, alice_change, 49, &bob, 10, 1);
+    let estimate = blockchain.estimate_fee(1);
+    println!(
+        "Fee estimate for the next block: {} - {} for a {}-byte payment",
+        estimate,
+        estimate.fee_for(transaction_3.size()),
+        transaction_3.size()
+    );
+    blockchain.add_transaction(transaction_3)?;
+    blockchain.mine_and_add_block(miner)?;
+    if let Some(report) = reports.try_iter().last() {
+        println!("Last block: {} hashes at {:.0} hashes per second", report.hashes, report.hashrate());
     }
-}
 
-/// Returns the current timestamp in seconds since UNIX_EPOCH.
-fn current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs()
-}
-
-fn main() {
-    let mut blockchain = Blockchain::new(4); // Difficulty level set to 4
-
-    blockchain.add_transaction("Transaction 1".to_string());
-    blockchain.add_transaction("Transaction 2".to_string());
-    blockchain.mine_and_add_block();
-
-    blockchain.add_transaction("Transaction 3".to_string());
-    blockchain.mine_and_add_block();
-
-    println!("Blockchain:");
+    println!("Blockchain ({}, total work {}):", blockchain.pow.kind(), blockchain.total_work());
     for block in &blockchain.chain {
         println!(
-            "Block {} - Hash: {} - Data: {} - Nonce: {}",
-            block.index, block.hash, block.data, block.nonce
+            "Block {} - Hash: {} - Transactions: {} - Version: {:#010x} - Nonce: {}",
+            block.height(),
+            block.calculate_hash(&blockchain.pow),
+            block.transactions.len(),
+            block.header.version,
+            block.header.nonce
         );
     }
+    blockchain.validate()?;
+    println!("Chain is valid.");
+    if let Some(state) = blockchain.deployment_state("demo") {
+        println!("Deployment demo: {}", state);
+    }
+    println!(
+        "Spendable - Alice: {} - Bob: {} - Miner: {} - Supply: {}",
+        blockchain.balance(&Address::of(&alice)),
+        blockchain.balance(&Address::of(&bob)),
+        blockchain.balance(&miner),
+        blockchain.supply
+    );
+
+    // A new full node replays the blocks as a peer would send them and reaches the same state.
+    let full_node = Blockchain::from_blocks(blockchain.pow.clone(), blockchain.params.clone(), blockchain.chain.clone())?;
+    println!(
+        "Full node: {} unspent outputs - State root: {} - Matches: {}",
+        full_node.state.utxos().map_or(0, |utxos| utxos.len()),
+        full_node.state.state_root(),
+        full_node.state == blockchain.state
+    );
+
+    // A light client syncs the headers alone, as a peer would send them, and checks the proof against them.
+    let headers = blockchain
+        .headers()
+        .map(|header| BlockHeader::decode(&header.encode()))
+        .collect();
+    let light_client = HeaderChain::from_headers(blockchain.pow.clone(), blockchain.params.clone(), headers)?;
+    println!(
+        "Light client: {} headers - Tip: {} - Total work: {} - Next bits: {:#010x}",
+        light_client.headers().len(),
+        light_client.tip_hash(),
+        light_client.total_work(),
+        light_client.next_bits()?
+    );
+    if let Some(state) = light_client.deployment_state("demo") {
+        println!("Light client deployment demo: {}", state);
+    }
+    let proof = blockchain.prove_inclusion(&transaction_2.id())?;
+    println!(
+        "Transaction {} inclusion in block {}: {}",
+        proof.transaction.id(),
+        proof.header.height,
+        light_client.verify_inclusion(&proof)
+    );
+    Ok(())
 }
@@ -0,0 +1,981 @@
+// Unconfirmed transactions waiting to be mined
+
+use crate::account::{self, Account};
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::state::{ChainState, StateModel};
+use crate::transaction::{Address, OutPoint, Transaction, TxOutput};
+use crate::utxo::Utxo;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::slice;
+
+/// Local limits of a mempool. Unlike `ChainParams` they are policy, not consensus:
+/// nodes may choose them differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolConfig {
+    /// Most bytes of transactions the pool holds; beyond it the lowest fee rates are evicted.
+    pub max_bytes: usize,
+    /// Seconds a transaction may wait in the pool before it is dropped.
+    pub expiry: u64,
+}
+
+impl Default for MempoolConfig {
+    /// 32 MB of transactions, each kept for up to two weeks.
+    fn default() -> Self {
+        MempoolConfig {
+            max_bytes: 32_000_000,
+            expiry: 14 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// A transaction waiting in the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolEntry {
+    pub transaction: Transaction,
+    pub id: Hash256,
+    /// Encoded size in bytes; see `Transaction::size`.
+    pub size: usize,
+    /// Unix time the transaction entered the pool.
+    pub added: u64,
+    sequence: u64,
+}
+
+impl MempoolEntry {
+    /// Compares fee rates, fee per byte.
+    pub fn cmp_fee_rate(&self, other: &MempoolEntry) -> Ordering {
+        cmp_fee_rate(
+            self.transaction.fee as u128,
+            self.size as u128,
+            other.transaction.fee as u128,
+            other.size as u128,
+        )
+    }
+}
+
+/// An entry scored by the fee rate of its package: the entry together with its
+/// ancestors that are not yet in a block template. Greater is better, and among
+/// equal rates the entry that arrived first is greater.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    id: Hash256,
+    sequence: u64,
+    /// Fees of the package.
+    fee: u128,
+    /// Encoded size of the package.
+    size: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_fee_rate(self.fee, self.size as u128, other.fee, other.size as u128).then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+/// What the pool's entries add to and take from one account, on account chains.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Pending {
+    /// Number of entries the account sent.
+    sent: u64,
+    /// What those entries debit: their outputs plus fees.
+    debits: u128,
+    /// What entries pay the account.
+    credits: u128,
+}
+
+impl Pending {
+    /// Adds what `transaction` does to `address`.
+    fn add(&mut self, transaction: &Transaction, address: &Address) {
+        if transaction.sender == *address {
+            self.sent += 1;
+            self.debits += debit(transaction);
+        }
+        self.credits += credit(transaction, address);
+    }
+
+    /// Takes away what `transaction` does to `address`.
+    fn remove(&mut self, transaction: &Transaction, address: &Address) {
+        if transaction.sender == *address {
+            self.sent -= 1;
+            self.debits -= debit(transaction);
+        }
+        self.credits -= credit(transaction, address);
+    }
+}
+
+/// Transactions that are valid on top of the chain state, applied in arrival order.
+///
+/// The pool keeps a view of what its entries do on top of the state: the outputs
+/// they create and spend, or the nonces they use and the balances they move. A new
+/// entry is checked against the state through that view, as if every entry were
+/// applied, so children always arrive after their parents. When entries leave the
+/// pool only the ones that may have depended on them are checked again: their
+/// descendants and, on account chains, whatever their recipients sent. A rechecked
+/// entry keeps its place, so it may then rely on a credit from a later entry.
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: HashMap<Hash256, MempoolEntry>,
+    arrival: BTreeMap<u64, Hash256>,
+    /// The entry spending each outpoint, to find double spends on UTXO chains.
+    spends: HashMap<OutPoint, Hash256>,
+    /// The outputs entries create, which later entries may spend, on UTXO chains.
+    outputs: HashMap<OutPoint, TxOutput>,
+    /// The entry using each sender's nonce, to find double spends on account chains.
+    nonces: HashMap<(Address, u64), Hash256>,
+    /// What the entries do to each account they touch, on account chains.
+    pending: HashMap<Address, Pending>,
+    bytes: usize,
+    next_sequence: u64,
+}
+
+impl Mempool {
+    /// Creates an empty pool with the given limits.
+    pub fn new(config: MempoolConfig) -> Self {
+        Mempool {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// The limits the pool was created with.
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    /// Number of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the pool holds no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total encoded size of the transactions in the pool.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns `true` if the pool holds the transaction with id `tx_id`.
+    pub fn contains(&self, tx_id: &Hash256) -> bool {
+        self.entries.contains_key(tx_id)
+    }
+
+    /// Returns the entry for the transaction with id `tx_id`, if any.
+    pub fn get(&self, tx_id: &Hash256) -> Option<&MempoolEntry> {
+        self.entries.get(tx_id)
+    }
+
+    /// The entries in arrival order, in which they apply on top of the chain state.
+    pub fn entries(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.arrival.values().map(|id| &self.entries[id])
+    }
+
+    /// The entry `tx_id` together with its ancestors in the pool, in arrival order:
+    /// the package a block has to include for it. Empty if it is not in the pool.
+    pub fn ancestor_package(&self, tx_id: &Hash256) -> Vec<&MempoolEntry> {
+        if !self.contains(tx_id) {
+            return Vec::new();
+        }
+        let mut package: Vec<&MempoolEntry> = self.ancestors(tx_id).iter().map(|id| &self.entries[id]).collect();
+        package.sort_by_key(|member| member.sequence);
+        package
+    }
+
+    /// Adds a transaction that a block at `height` could include after the
+    /// transactions already in the pool. `now` is the current Unix time.
+    ///
+    /// Rejects coinbases, bad signatures and transactions already in the pool. A
+    /// transaction spending what pool entries already spend replaces them, along
+    /// with their descendants, only if every one of them opted in to replacement
+    /// and it pays more than all of them together and a higher fee rate than each.
+    /// Entries past their expiry leave the pool as the transaction enters it.
+    ///
+    /// If the pool would grow past its size limit, entries are evicted together with
+    /// their descendants, which cannot stay without them, lowest package fee rate
+    /// first, as long as that rate is strictly lower than the transaction's. The
+    /// transaction's own ancestors are never evicted. It is turned away if that does
+    /// not free enough room.
+    ///
+    /// On error the pool is left unchanged.
+    pub fn insert(&mut self, transaction: Transaction, state: &ChainState, height: u32, now: u64) -> Result<(), ChainError> {
+        let tx_id = transaction.id();
+        if transaction.is_coinbase() {
+            return Err(ChainError::UnexpectedCoinbase(tx_id));
+        }
+        if !transaction.verify_signature() {
+            return Err(ChainError::InvalidSignature(tx_id));
+        }
+        if self.entries.contains_key(&tx_id) {
+            return Err(ChainError::DuplicateTransaction(tx_id));
+        }
+        let entry = MempoolEntry {
+            size: transaction.size(),
+            transaction,
+            id: tx_id,
+            added: now,
+            sequence: self.next_sequence,
+        };
+        // Work out everything that leaves the pool before changing it.
+        let expired = self.expired(now);
+        let replaced = self.replaced_by(&entry, state.model(), &expired)?;
+        let leaving: HashSet<Hash256> = expired.union(&replaced).copied().collect();
+        self.check(&entry.transaction, state, height, &leaving)?;
+        let leaving_bytes: usize = leaving.iter().map(|id| self.entries[id].size).sum();
+        let excess = (self.bytes - leaving_bytes + entry.size).saturating_sub(self.config.max_bytes);
+        let evicted = if excess > 0 {
+            self.eviction_candidates(&entry, excess, &leaving)
+                .ok_or(ChainError::MempoolFull(tx_id))?
+        } else {
+            HashSet::new()
+        };
+
+        let removed: Vec<MempoolEntry> = leaving.iter().chain(&evicted).filter_map(|id| self.remove(id)).collect();
+        self.next_sequence += 1;
+        self.add(entry, state.model());
+        let mut dropped = self.recheck(&removed, state, height);
+        let Some(position) = dropped.iter().position(|entry| entry.id == tx_id) else {
+            return Ok(());
+        };
+        // On account chains the transaction may have relied on a credit from an entry
+        // that failed once the others left. Put everything back as it was.
+        let entry = dropped.swap_remove(position);
+        let excluded: HashSet<Hash256> = removed.iter().chain(&dropped).map(|entry| entry.id).collect();
+        for gone in removed.into_iter().chain(dropped) {
+            self.add(gone, state.model());
+        }
+        self.next_sequence -= 1;
+        if !evicted.is_empty() {
+            return Err(ChainError::MempoolFull(tx_id));
+        }
+        self.check(&entry.transaction, state, height, &excluded)?;
+        Err(ChainError::MempoolFull(tx_id))
+    }
+
+    /// Drops the transactions that have waited longer than the configured expiry,
+    /// along with anything spending their outputs.
+    pub fn expire(&mut self, now: u64, state: &ChainState, height: u32) {
+        let gone: Vec<MempoolEntry> = self.expired(now).iter().filter_map(|id| self.remove(id)).collect();
+        self.recheck(&gone, state, height);
+    }
+
+    /// The entries that have waited longer than the configured expiry at `now`,
+    /// together with their descendants.
+    fn expired(&self, now: u64) -> HashSet<Hash256> {
+        let expiry = self.config.expiry;
+        self.entries
+            .values()
+            .filter(|entry| entry.added.saturating_add(expiry) <= now)
+            .flat_map(|entry| closure(entry.id, |id| self.children(&self.entries[id])))
+            .collect()
+    }
+
+    /// Updates the pool after a block was connected to `state`: removes the
+    /// block's transactions and everything that no longer applies on top of it,
+    /// such as transactions conflicting with the block. `height` is the height of
+    /// the next block.
+    pub fn remove_confirmed(&mut self, transactions: &[Transaction], state: &ChainState, height: u32) {
+        let mut conflicts = Vec::new();
+        for transaction in transactions.iter().filter(|transaction| !transaction.is_coinbase()) {
+            // What the pool held as is still applies: the block did what it would have.
+            if self.remove(&transaction.id()).is_some() {
+                continue;
+            }
+            conflicts.extend(transaction.inputs.iter().filter_map(|input| self.spends.get(input).copied()));
+            conflicts.extend(self.nonces.get(&(transaction.sender, transaction.nonce)).copied());
+        }
+        let gone: Vec<MempoolEntry> = conflicts.iter().filter_map(|id| self.remove(id)).collect();
+        self.recheck(&gone, state, height);
+    }
+
+    /// Picks transactions for a block at `height` whose encoded sizes add up to at
+    /// most `max_bytes`, by the fee rate of ancestor packages.
+    ///
+    /// A transaction is scored together with its ancestors that are not yet picked,
+    /// and the best scoring package is picked whole, parents first. A high-fee child
+    /// thereby pulls in a low-fee parent it could not be mined without. Package fees
+    /// and sizes are summed once; picking an entry only rescores its descendants.
+    /// The result applies in order on top of `state`; packages are tried on a copy of it.
+    pub fn build_template(&self, state: &ChainState, height: u32, max_bytes: usize) -> Vec<Transaction> {
+        let mut state = state.clone();
+        let ancestors: HashMap<Hash256, HashSet<Hash256>> =
+            self.entries.keys().map(|id| (*id, self.ancestors(id))).collect();
+        // The fee and size of every package, reduced for its descendants whenever
+        // an entry is picked, like the modified entries of Bitcoin Core's miner.
+        let mut packages: HashMap<Hash256, (u128, usize)> = ancestors
+            .iter()
+            .map(|(id, ancestors)| {
+                let members = ancestors.iter().map(|id| &self.entries[id]);
+                let fee = members.clone().map(|member| member.transaction.fee as u128).sum();
+                (*id, (fee, members.map(|member| member.size).sum()))
+            })
+            .collect();
+        let candidate = |id: &Hash256, (fee, size): (u128, usize)| Candidate {
+            id: *id,
+            sequence: self.entries[id].sequence,
+            fee,
+            size,
+        };
+        let mut queue: BinaryHeap<Candidate> = packages.iter().map(|(id, package)| candidate(id, *package)).collect();
+        // Entries that failed to apply, by sender, until something pays the sender.
+        let mut deferred: HashMap<Address, Vec<Hash256>> = HashMap::new();
+        let mut picked: HashSet<Hash256> = HashSet::new();
+        let mut selected = Vec::new();
+        let mut used = 0;
+        while let Some(best) = queue.pop() {
+            // Scores are queued again when they change, so skip the outdated ones.
+            if picked.contains(&best.id) || packages[&best.id] != (best.fee, best.size) || used + best.size > max_bytes {
+                continue;
+            }
+            let mut members: Vec<&MempoolEntry> = ancestors[&best.id]
+                .iter()
+                .filter(|id| !picked.contains(id))
+                .map(|id| &self.entries[id])
+                .collect();
+            members.sort_by_key(|member| member.sequence);
+            if !apply_all(&mut state, height, &members) {
+                // On account chains it may need a credit from an entry not picked yet.
+                deferred.entry(self.entries[&best.id].transaction.sender).or_default().push(best.id);
+                continue;
+            };
+            used += best.size;
+            for member in members {
+                picked.insert(member.id);
+                selected.push(member.transaction.clone());
+                for id in closure(member.id, |id| self.children(&self.entries[id])) {
+                    if !picked.contains(&id) {
+                        let package = packages.get_mut(&id).expect("every entry has a package");
+                        package.0 -= member.transaction.fee as u128;
+                        package.1 -= member.size;
+                        queue.push(candidate(&id, *package));
+                    }
+                }
+                for output in &member.transaction.outputs {
+                    for id in deferred.remove(&output.recipient).unwrap_or_default() {
+                        queue.push(candidate(&id, packages[&id]));
+                    }
+                }
+            }
+        }
+        selected
+    }
+
+    /// Checks that `entry` may replace the pool entries it conflicts with, other
+    /// than the `expired` ones, and returns those entries together with their
+    /// descendants.
+    fn replaced_by(
+        &self,
+        entry: &MempoolEntry,
+        model: StateModel,
+        expired: &HashSet<Hash256>,
+    ) -> Result<HashSet<Hash256>, ChainError> {
+        let transaction = &entry.transaction;
+        let mut conflicts: Vec<Hash256> = match model {
+            StateModel::Utxo => transaction.inputs.iter().filter_map(|input| self.spends.get(input).copied()).collect(),
+            StateModel::Account => self.nonces.get(&(transaction.sender, transaction.nonce)).copied().into_iter().collect(),
+        };
+        conflicts.retain(|id| !expired.contains(id));
+        conflicts.sort();
+        conflicts.dedup();
+
+        let mut replaced = HashSet::new();
+        let mut required = 0u128;
+        for id in &conflicts {
+            let existing = &self.entries[id];
+            if !existing.transaction.replaceable {
+                return Err(ChainError::MempoolConflict {
+                    tx_id: entry.id,
+                    existing: *id,
+                });
+            }
+            // The least fee giving a strictly higher fee rate than the existing entry.
+            let rate_floor = existing.transaction.fee as u128 * entry.size as u128 / existing.size as u128 + 1;
+            required = required.max(rate_floor);
+            replaced.extend(closure(*id, |id| self.children(&self.entries[id])));
+        }
+        if !replaced.is_empty() {
+            let replaced_fees = replaced.iter().map(|id| self.entries[id].transaction.fee as u128).sum::<u128>();
+            required = required.max(replaced_fees + 1);
+        }
+        if (transaction.fee as u128) < required {
+            return Err(ChainError::ReplacementFeeTooLow {
+                tx_id: entry.id,
+                fee: transaction.fee,
+                required,
+            });
+        }
+        Ok(replaced)
+    }
+
+    /// Checks that `transaction` applies on top of `state` with every entry but
+    /// the `excluded` ones applied.
+    fn check(
+        &self,
+        transaction: &Transaction,
+        state: &ChainState,
+        height: u32,
+        excluded: &HashSet<Hash256>,
+    ) -> Result<(), ChainError> {
+        match state {
+            ChainState::Utxo(utxos) => utxos.check_spend(transaction, height, |input| {
+                if self.spends.get(input).is_some_and(|spender| !excluded.contains(spender)) {
+                    return None;
+                }
+                match self.outputs.get(input) {
+                    Some(output) if !excluded.contains(&input.tx_id) => Some(Utxo {
+                        output: *output,
+                        height,
+                        coinbase: false,
+                    }),
+                    Some(_) => None,
+                    None => utxos.get(input).copied(),
+                }
+            }),
+            ChainState::Account(accounts) => {
+                let sender = &transaction.sender;
+                let mut pending = self.pending.get(sender).copied().unwrap_or_default();
+                for id in excluded {
+                    pending.remove(&self.entries[id].transaction, sender);
+                }
+                let balance = (accounts.balance(sender, height) as u128 + pending.credits).saturating_sub(pending.debits);
+                let account = Account {
+                    balance: balance.min(u64::MAX as u128) as u64,
+                    nonce: accounts.get(sender).nonce + pending.sent,
+                };
+                account::check_transfer(transaction, &account).map(|_| ())
+            }
+        }
+    }
+
+    /// The entries `transaction` spends outputs of or, on an account chain, whose
+    /// nonce directly precedes its own.
+    ///
+    /// On account chains a transaction may also depend on a balance credited by
+    /// another entry; such links are not tracked, and only show up as the
+    /// transaction failing to apply until the other entry has.
+    fn parents(&self, transaction: &Transaction) -> Vec<Hash256> {
+        let mut parents: Vec<Hash256> = transaction
+            .inputs
+            .iter()
+            .map(|input| input.tx_id)
+            .filter(|id| self.entries.contains_key(id))
+            .collect();
+        if let Some(previous) = transaction.nonce.checked_sub(1) {
+            parents.extend(self.nonces.get(&(transaction.sender, previous)));
+        }
+        parents.sort();
+        parents.dedup();
+        parents
+    }
+
+    /// The entries spending outputs of `entry` or, on an account chain, using the
+    /// nonce after its own. `entry` need not be in the pool any more.
+    fn children(&self, entry: &MempoolEntry) -> Vec<Hash256> {
+        let transaction = &entry.transaction;
+        let mut children: Vec<Hash256> = (0..transaction.outputs.len() as u32)
+            .filter_map(|index| self.spends.get(&OutPoint { tx_id: entry.id, index }).copied())
+            .collect();
+        if let Some(next) = transaction.nonce.checked_add(1) {
+            children.extend(self.nonces.get(&(transaction.sender, next)));
+        }
+        children
+    }
+
+    /// The entry `tx_id` and every pool entry it descends from.
+    fn ancestors(&self, tx_id: &Hash256) -> HashSet<Hash256> {
+        closure(*tx_id, |id| self.parents(&self.entries[id].transaction))
+    }
+
+    /// Chooses entries to evict for `entry` until they free at least `excess` bytes,
+    /// or returns `None` if they cannot.
+    ///
+    /// An entry is scored by the fee rate of its descendant package: the entry and
+    /// its descendants, which are evicted with it. Packages go lowest rate first and
+    /// newest first among equals, while their rate is strictly lower than `entry`'s;
+    /// evicting one rescores the packages of its ancestors. The `excluded` entries,
+    /// which leave the pool anyway, and the ancestors of `entry` are left alone.
+    fn eviction_candidates(
+        &self,
+        entry: &MempoolEntry,
+        excess: usize,
+        excluded: &HashSet<Hash256>,
+    ) -> Option<HashSet<Hash256>> {
+        let protected: HashSet<Hash256> =
+            self.parents(&entry.transaction).iter().flat_map(|id| self.ancestors(id)).collect();
+        let descendants = |id: &Hash256| -> Vec<Hash256> {
+            let mut descendants: Vec<Hash256> = closure(*id, |id| self.children(&self.entries[id]))
+                .into_iter()
+                .filter(|id| !excluded.contains(id))
+                .collect();
+            descendants.sort();
+            descendants
+        };
+        let mut packages: HashMap<Hash256, (u128, usize)> = self
+            .entries
+            .keys()
+            .filter(|id| !excluded.contains(id) && !protected.contains(id))
+            .map(|id| {
+                let members = descendants(id).into_iter().map(|id| &self.entries[&id]);
+                let fee = members.clone().map(|member| member.transaction.fee as u128).sum();
+                (*id, (fee, members.map(|member| member.size).sum()))
+            })
+            .collect();
+        let candidate = |id: &Hash256, (fee, size): (u128, usize)| {
+            Reverse(Candidate {
+                id: *id,
+                sequence: self.entries[id].sequence,
+                fee,
+                size,
+            })
+        };
+        let mut queue: BinaryHeap<Reverse<Candidate>> =
+            packages.iter().map(|(id, package)| candidate(id, *package)).collect();
+        let mut evicted = HashSet::new();
+        let mut freed = 0;
+        while freed < excess {
+            let Reverse(worst) = queue.pop()?;
+            // Scores are queued again when they change, so skip the outdated ones.
+            if evicted.contains(&worst.id) || packages[&worst.id] != (worst.fee, worst.size) {
+                continue;
+            }
+            if cmp_fee_rate(worst.fee, worst.size as u128, entry.transaction.fee as u128, entry.size as u128)
+                != Ordering::Less
+            {
+                return None;
+            }
+            let members: Vec<Hash256> = descendants(&worst.id).into_iter().filter(|id| evicted.insert(*id)).collect();
+            for id in members {
+                let member = &self.entries[&id];
+                freed += member.size;
+                for ancestor in self.ancestors(&id) {
+                    if evicted.contains(&ancestor) {
+                        continue;
+                    }
+                    if let Some(package) = packages.get_mut(&ancestor) {
+                        package.0 -= member.transaction.fee as u128;
+                        package.1 -= member.size;
+                        queue.push(candidate(&ancestor, *package));
+                    }
+                }
+            }
+        }
+        Some(evicted)
+    }
+
+    /// Checks the entries that may have depended on the `gone` ones again and drops
+    /// those that no longer apply, then does the same for what those dropped.
+    /// Returns every entry it dropped.
+    ///
+    /// The suspects are the descendants of what left and, on account chains, the
+    /// entries its recipients sent, whose balance it may have covered. They are
+    /// taken out and put back in arrival order if they apply on top of the rest.
+    fn recheck(&mut self, gone: &[MempoolEntry], state: &ChainState, height: u32) -> Vec<MempoolEntry> {
+        let mut dropped: Vec<MempoolEntry> = Vec::new();
+        // What left in the last round: `gone` at first, then the entries dropped.
+        let mut last_round = None;
+        loop {
+            let gone = match last_round.clone() {
+                None => gone,
+                Some(range) => &dropped[range],
+            };
+            let mut roots: Vec<Hash256> = gone.iter().flat_map(|entry| self.children(entry)).collect();
+            if let ChainState::Account(accounts) = state {
+                for output in gone.iter().flat_map(|entry| &entry.transaction.outputs) {
+                    let first = accounts.get(&output.recipient).nonce;
+                    roots.extend(self.nonces.get(&(output.recipient, first)));
+                }
+            }
+            let suspects: HashSet<Hash256> =
+                roots.into_iter().flat_map(|id| closure(id, |id| self.children(&self.entries[id]))).collect();
+            let mut entries: Vec<MempoolEntry> = suspects.iter().filter_map(|id| self.remove(id)).collect();
+            entries.sort_by_key(|entry| entry.sequence);
+            let start = dropped.len();
+            for entry in entries {
+                if self.check(&entry.transaction, state, height, &HashSet::new()).is_ok() {
+                    self.add(entry, state.model());
+                } else {
+                    dropped.push(entry);
+                }
+            }
+            if dropped.len() == start {
+                return dropped;
+            }
+            last_round = Some(start..dropped.len());
+        }
+    }
+
+    fn add(&mut self, entry: MempoolEntry, model: StateModel) {
+        let transaction = &entry.transaction;
+        match model {
+            StateModel::Utxo => {
+                for input in &transaction.inputs {
+                    self.spends.insert(*input, entry.id);
+                }
+                for (index, output) in transaction.outputs.iter().enumerate() {
+                    let outpoint = OutPoint {
+                        tx_id: entry.id,
+                        index: index as u32,
+                    };
+                    self.outputs.insert(outpoint, *output);
+                }
+            }
+            StateModel::Account => {
+                self.nonces.insert((transaction.sender, transaction.nonce), entry.id);
+                for address in touched(transaction) {
+                    self.pending.entry(address).or_default().add(transaction, &address);
+                }
+            }
+        }
+        self.bytes += entry.size;
+        self.arrival.insert(entry.sequence, entry.id);
+        self.entries.insert(entry.id, entry);
+    }
+
+    fn remove(&mut self, tx_id: &Hash256) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(tx_id)?;
+        let transaction = &entry.transaction;
+        for input in &transaction.inputs {
+            if self.spends.get(input) == Some(tx_id) {
+                self.spends.remove(input);
+            }
+        }
+        for index in 0..transaction.outputs.len() as u32 {
+            self.outputs.remove(&OutPoint { tx_id: *tx_id, index });
+        }
+        let key = (transaction.sender, transaction.nonce);
+        if self.nonces.get(&key) == Some(tx_id) {
+            self.nonces.remove(&key);
+            for address in touched(transaction) {
+                if let Some(pending) = self.pending.get_mut(&address) {
+                    pending.remove(transaction, &address);
+                    if *pending == Pending::default() {
+                        self.pending.remove(&address);
+                    }
+                }
+            }
+        }
+        self.bytes -= entry.size;
+        self.arrival.remove(&entry.sequence);
+        Some(entry)
+    }
+}
+
+/// Compares the fee rates `fee_a / size_a` and `fee_b / size_b` exactly, without rounding.
+fn cmp_fee_rate(fee_a: u128, size_a: u128, fee_b: u128, size_b: u128) -> Ordering {
+    (fee_a * size_b).cmp(&(fee_b * size_a))
+}
+
+/// What `transaction` debits from its sender: its outputs plus the fee.
+fn debit(transaction: &Transaction) -> u128 {
+    transaction
+        .outputs
+        .iter()
+        .fold(transaction.fee as u128, |total, output| total + output.amount as u128)
+}
+
+/// What `transaction` pays `address`.
+fn credit(transaction: &Transaction, address: &Address) -> u128 {
+    transaction
+        .outputs
+        .iter()
+        .filter(|output| output.recipient == *address)
+        .map(|output| output.amount as u128)
+        .sum()
+}
+
+/// The sender and recipients of `transaction`, each once.
+fn touched(transaction: &Transaction) -> Vec<Address> {
+    let mut addresses: Vec<Address> = transaction.outputs.iter().map(|output| output.recipient).collect();
+    addresses.push(transaction.sender);
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// Applies `members` in order and returns `true`, or applies nothing and returns
+/// `false` if one of them fails to apply.
+fn apply_all(state: &mut ChainState, height: u32, members: &[&MempoolEntry]) -> bool {
+    let mut undos = Vec::new();
+    for member in members {
+        match state.connect(height, slice::from_ref(&member.transaction)) {
+            Ok(undo) => undos.push(undo),
+            Err(_) => {
+                for undo in undos.into_iter().rev() {
+                    state.disconnect(undo);
+                }
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// `start` and everything reachable from it by repeatedly following `next`.
+fn closure(start: Hash256, next: impl Fn(&Hash256) -> Vec<Hash256>) -> HashSet<Hash256> {
+    let mut seen = HashSet::from([start]);
+    let mut queue = vec![start];
+    while let Some(id) = queue.pop() {
+        for other in next(&id) {
+            if seen.insert(other) {
+                queue.push(other);
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::params::ChainParams;
+    use crate::pow::PowKind;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_seed(&[seed; 32])
+    }
+
+    fn pay(key: &SigningKey, amount: u64) -> TxOutput {
+        TxOutput {
+            recipient: Address::of(key),
+            amount,
+        }
+    }
+
+    /// A state after a genesis block paying `outputs`, and its coinbase.
+    fn genesis(model: StateModel, outputs: Vec<TxOutput>) -> (ChainState, Transaction) {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.state_model = model;
+        let coinbase = Transaction::coinbase(0, outputs);
+        let mut state = ChainState::new(&params);
+        state.connect(0, slice::from_ref(&coinbase)).unwrap();
+        (state, coinbase)
+    }
+
+    #[test]
+    fn replacing_or_confirming_a_conflict_drops_the_descendants() {
+        let (alice, bob) = (key(1), key(2));
+        let (mut state, coinbase) = genesis(StateModel::Utxo, vec![pay(&alice, 100)]);
+        let coin = OutPoint {
+            tx_id: coinbase.id(),
+            index: 0,
+        };
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let parent = Transaction::new_replaceable(&alice, vec![coin], vec![pay(&alice, 95)], 5, 0);
+        let spend = |tx: &Transaction| vec![OutPoint { tx_id: tx.id(), index: 0 }];
+        let child = Transaction::new_signed(&alice, spend(&parent), vec![pay(&bob, 90)], 5, 0);
+        pool.insert(parent.clone(), &state, 1, 0).unwrap();
+        pool.insert(child.clone(), &state, 1, 0).unwrap();
+        assert_eq!(pool.len(), 2);
+
+        let replacement = Transaction::new_signed(&alice, vec![coin], vec![pay(&bob, 80)], 20, 1);
+        pool.insert(replacement.clone(), &state, 1, 0).unwrap();
+        assert!(pool.contains(&replacement.id()) && !pool.contains(&parent.id()) && !pool.contains(&child.id()));
+        let again = Transaction::new_signed(&alice, vec![coin], vec![pay(&bob, 50)], 50, 2);
+        assert_eq!(
+            pool.insert(again.clone(), &state, 1, 0),
+            Err(ChainError::MempoolConflict {
+                tx_id: again.id(),
+                existing: replacement.id(),
+            })
+        );
+        let grandchild = Transaction::new_signed(&bob, spend(&replacement), vec![pay(&alice, 75)], 5, 0);
+        pool.insert(grandchild, &state, 1, 0).unwrap();
+        assert_eq!(pool.len(), 2);
+
+        // A block spends the coin some other way.
+        let mined = Transaction::new_signed(&alice, vec![coin], vec![pay(&alice, 99)], 1, 3);
+        state.connect(1, slice::from_ref(&mined)).unwrap();
+        pool.remove_confirmed(slice::from_ref(&mined), &state, 2);
+        assert!(pool.is_empty());
+        assert_eq!(pool.bytes(), 0);
+    }
+
+    #[test]
+    fn a_replacement_pays_for_everything_it_evicts() {
+        let (alice, bob) = (key(1), key(2));
+        let (state, coinbase) = genesis(StateModel::Utxo, vec![pay(&alice, 100)]);
+        let coin = OutPoint {
+            tx_id: coinbase.id(),
+            index: 0,
+        };
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let parent = Transaction::new_replaceable(&alice, vec![coin], vec![pay(&alice, 95)], 5, 0);
+        let child_input = vec![OutPoint {
+            tx_id: parent.id(),
+            index: 0,
+        }];
+        let child = Transaction::new_signed(&alice, child_input, vec![pay(&bob, 90)], 5, 0);
+        pool.insert(parent, &state, 1, 0).unwrap();
+        pool.insert(child, &state, 1, 0).unwrap();
+
+        // Beating the parent's fee rate is not enough: the child's fee goes too.
+        let cheap = Transaction::new_replaceable(&alice, vec![coin], vec![pay(&bob, 90)], 10, 1);
+        assert_eq!(
+            pool.insert(cheap.clone(), &state, 1, 0),
+            Err(ChainError::ReplacementFeeTooLow {
+                tx_id: cheap.id(),
+                fee: 10,
+                required: 11,
+            })
+        );
+        assert_eq!(pool.len(), 2);
+        let enough = Transaction::new_replaceable(&alice, vec![coin], vec![pay(&bob, 89)], 11, 1);
+        pool.insert(enough.clone(), &state, 1, 0).unwrap();
+        assert_eq!(pool.entries().map(|entry| entry.id).collect::<Vec<_>>(), vec![enough.id()]);
+        assert_eq!(pool.bytes(), enough.size());
+    }
+
+    #[test]
+    fn account_entries_see_pending_nonces_and_credits() {
+        let (alice, bob, carol) = (key(1), key(2), key(3));
+        let (mut state, _) = genesis(StateModel::Account, vec![pay(&alice, 100)]);
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let to_bob = Transaction::new_signed(&alice, vec![], vec![pay(&bob, 50)], 1, 0);
+        let from_bob = Transaction::new_signed(&bob, vec![], vec![pay(&carol, 40)], 1, 0);
+        assert!(matches!(
+            pool.insert(from_bob.clone(), &state, 1, 0),
+            Err(ChainError::InsufficientBalance { .. })
+        ));
+        pool.insert(to_bob.clone(), &state, 1, 0).unwrap();
+        pool.insert(from_bob.clone(), &state, 1, 0).unwrap();
+        let skipped = Transaction::new_signed(&alice, vec![], vec![pay(&carol, 1)], 1, 2);
+        assert!(matches!(
+            pool.insert(skipped, &state, 1, 0),
+            Err(ChainError::BadNonce { expected: 1, .. })
+        ));
+        let overdraft = Transaction::new_signed(&alice, vec![], vec![pay(&carol, 49)], 1, 1);
+        assert!(matches!(
+            pool.insert(overdraft, &state, 1, 0),
+            Err(ChainError::InsufficientBalance { balance: 49, .. })
+        ));
+
+        // A block spends Alice's nonce on something else, so Bob is never paid.
+        let mined = Transaction::new_signed(&alice, vec![], vec![pay(&carol, 90)], 1, 0);
+        state.connect(1, slice::from_ref(&mined)).unwrap();
+        pool.remove_confirmed(slice::from_ref(&mined), &state, 2);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn a_high_fee_child_pulls_its_parent_into_the_template() {
+        let (alice, bob, carol) = (key(1), key(2), key(3));
+        let (state, coinbase) = genesis(StateModel::Utxo, vec![pay(&alice, 100), pay(&bob, 100)]);
+        let coin = |index| OutPoint {
+            tx_id: coinbase.id(),
+            index,
+        };
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let parent = Transaction::new_signed(&alice, vec![coin(0)], vec![pay(&alice, 99)], 1, 0);
+        let child_input = vec![OutPoint {
+            tx_id: parent.id(),
+            index: 0,
+        }];
+        let child = Transaction::new_signed(&alice, child_input, vec![pay(&carol, 69)], 30, 0);
+        let other = Transaction::new_signed(&bob, vec![coin(1)], vec![pay(&carol, 90)], 10, 0);
+        for transaction in [&parent, &other, &child] {
+            pool.insert(transaction.clone(), &state, 1, 0).unwrap();
+        }
+        let size = parent.size();
+        assert!(child.size() == size && other.size() == size);
+
+        // Parent and child pay 31 for two slots, 15.5 each, beating the 10 of the other.
+        let template = pool.build_template(&state, 1, 2 * size);
+        assert_eq!(template, vec![parent.clone(), child.clone()]);
+        // With one slot the child cannot go without its parent, so the other goes first.
+        assert_eq!(pool.build_template(&state, 1, size), vec![other.clone()]);
+        assert_eq!(pool.build_template(&state, 1, 3 * size), vec![parent, child, other]);
+    }
+
+    #[test]
+    fn eviction_takes_descendants_along_and_spares_cpfp_packages() {
+        let (alice, bob, carol) = (key(1), key(2), key(3));
+        let (state, coinbase) = genesis(StateModel::Utxo, vec![pay(&alice, 100), pay(&bob, 100), pay(&carol, 100)]);
+        let coin = |index| OutPoint {
+            tx_id: coinbase.id(),
+            index,
+        };
+        let parent = Transaction::new_signed(&alice, vec![coin(0)], vec![pay(&alice, 99)], 1, 0);
+        let child_input = vec![OutPoint {
+            tx_id: parent.id(),
+            index: 0,
+        }];
+        let child = Transaction::new_signed(&alice, child_input, vec![pay(&carol, 69)], 30, 0);
+        let other = Transaction::new_signed(&bob, vec![coin(1)], vec![pay(&carol, 95)], 5, 0);
+        let size = parent.size();
+        let mut pool = Mempool::new(MempoolConfig {
+            max_bytes: 3 * size,
+            ..MempoolConfig::default()
+        });
+        for transaction in [&parent, &child, &other] {
+            pool.insert(transaction.clone(), &state, 1, 0).unwrap();
+        }
+
+        // The parent alone pays least, but with its child it pays 15.5 per slot, so
+        // the other entry makes room.
+        let newcomer = Transaction::new_signed(&carol, vec![coin(2)], vec![pay(&bob, 80)], 20, 0);
+        pool.insert(newcomer.clone(), &state, 1, 0).unwrap();
+        let ids: HashSet<Hash256> = pool.entries().map(|entry| entry.id).collect();
+        assert_eq!(ids, HashSet::from([parent.id(), child.id(), newcomer.id()]));
+
+        // The parent's package now pays least, and goes whole.
+        let bigger = Transaction::new_signed(&bob, vec![coin(1)], vec![pay(&carol, 60)], 40, 1);
+        pool.insert(bigger.clone(), &state, 1, 0).unwrap();
+        let ids: HashSet<Hash256> = pool.entries().map(|entry| entry.id).collect();
+        assert_eq!(ids, HashSet::from([newcomer.id(), bigger.id()]));
+        assert_eq!(pool.bytes(), 2 * size);
+    }
+
+    #[test]
+    fn a_rejected_insert_leaves_the_pool_unchanged() {
+        let (alice, bob, carol) = (key(1), key(2), key(3));
+        let (state, coinbase) = genesis(StateModel::Utxo, vec![pay(&alice, 100), pay(&bob, 100)]);
+        let coin = |index| OutPoint {
+            tx_id: coinbase.id(),
+            index,
+        };
+        let parent = Transaction::new_signed(&alice, vec![coin(0)], vec![pay(&alice, 50), pay(&alice, 48)], 2, 0);
+        let other = Transaction::new_signed(&bob, vec![coin(1)], vec![pay(&carol, 90)], 10, 0);
+        let mut pool = Mempool::new(MempoolConfig {
+            max_bytes: parent.size() + other.size(),
+            ..MempoolConfig::default()
+        });
+        pool.insert(parent.clone(), &state, 1, 0).unwrap();
+        pool.insert(other.clone(), &state, 1, 0).unwrap();
+        let (len, bytes) = (pool.len(), pool.bytes());
+
+        // Only the parent pays a lower rate, and the child cannot stay without it.
+        let child_input = vec![OutPoint {
+            tx_id: parent.id(),
+            index: 0,
+        }];
+        let child = Transaction::new_signed(&alice, child_input, vec![pay(&carol, 45)], 5, 0);
+        assert_eq!(pool.insert(child.clone(), &state, 1, 0), Err(ChainError::MempoolFull(child.id())));
+        assert_eq!((pool.len(), pool.bytes()), (len, bytes));
+        assert!(pool.contains(&parent.id()) && pool.contains(&other.id()));
+        // Nor do expired entries leave when an insert fails.
+        let late = MempoolConfig::default().expiry;
+        assert!(matches!(
+            pool.insert(child, &state, 1, late),
+            Err(ChainError::MissingInput { .. })
+        ));
+        assert_eq!((pool.len(), pool.bytes()), (len, bytes));
+    }
+}
@@ -0,0 +1,200 @@
+// Merkle tree commitment to a block's transactions
+
+use crate::block::BlockHeader;
+use crate::crypto::sha256::sha256d;
+use crate::hash::Hash256;
+use crate::transaction::Transaction;
+
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+/// Hashes a transaction's witness id into a leaf of the tree.
+pub fn leaf_hash(id: &Hash256) -> Hash256 {
+    let mut bytes = [0u8; 33];
+    bytes[0] = LEAF_TAG;
+    bytes[1..].copy_from_slice(id.as_bytes());
+    Hash256(sha256d(&bytes))
+}
+
+/// Hashes two child nodes into their parent.
+pub fn node_hash(left: &Hash256, right: &Hash256) -> Hash256 {
+    let mut bytes = [0u8; 65];
+    bytes[0] = NODE_TAG;
+    bytes[1..33].copy_from_slice(left.as_bytes());
+    bytes[33..].copy_from_slice(right.as_bytes());
+    Hash256(sha256d(&bytes))
+}
+
+/// Computes the Merkle root over transaction witness ids, in block order.
+///
+/// Leaves and inner nodes are hashed with distinct tag bytes, so a leaf can never
+/// pass for an inner node. A node without a sibling is carried up to the next
+/// level unchanged rather than paired with itself as Bitcoin does, so no two
+/// distinct transaction lists share a root. An empty list has the all-zero root.
+pub fn merkle_root(ids: &[Hash256]) -> Hash256 {
+    if ids.is_empty() {
+        return Hash256::ZERO;
+    }
+    let mut level: Vec<Hash256> = ids.iter().map(leaf_hash).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+fn next_level(level: &[Hash256]) -> Vec<Hash256> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// The sibling hashes linking one leaf to the Merkle root.
+///
+/// A branch that leads to a header's root proves the transaction is in that
+/// block, and nothing more. The header does not commit to the number of
+/// transactions, so a different position and tree size can lead to the same
+/// root: for three transactions, the third one's branch also verifies as the
+/// second of two. The position is therefore kept private rather than offered
+/// as something a verified proof establishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBranch {
+    /// Claimed position of the leaf among the block's transactions.
+    leaf_index: u32,
+    /// Claimed number of transactions in the block, which fixes the shape of the tree.
+    leaf_count: u32,
+    /// Siblings from the leaf level upwards, skipping levels where the node was
+    /// carried up without one.
+    siblings: Vec<Hash256>,
+}
+
+impl MerkleBranch {
+    /// Builds the branch for the leaf at `index` of `ids`, or `None` if out of range.
+    pub fn new(ids: &[Hash256], index: usize) -> Option<Self> {
+        if index >= ids.len() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        let mut level: Vec<Hash256> = ids.iter().map(leaf_hash).collect();
+        let mut position = index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+        Some(MerkleBranch {
+            leaf_index: index as u32,
+            leaf_count: ids.len() as u32,
+            siblings,
+        })
+    }
+
+    /// Recomputes the root the branch leads to from transaction id `id`, or `None`
+    /// if the branch is malformed for its claimed position and tree size.
+    pub fn root(&self, id: &Hash256) -> Option<Hash256> {
+        if self.leaf_index >= self.leaf_count {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf_hash(id);
+        let mut position = self.leaf_index;
+        let mut width = self.leaf_count;
+        while width > 1 {
+            // The last node of an odd-width level has no sibling.
+            if position % 2 == 1 {
+                hash = node_hash(siblings.next()?, &hash);
+            } else if position + 1 < width {
+                hash = node_hash(&hash, siblings.next()?);
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none().then_some(hash)
+    }
+}
+
+/// Evidence that a transaction is committed to by a block header, though not
+/// of where in the block it is; see `MerkleBranch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    /// The transaction as included, signature and all.
+    pub transaction: Transaction,
+    pub header: BlockHeader,
+    pub branch: MerkleBranch,
+}
+
+/// Checks that `proof` shows its transaction is committed to by `header`.
+///
+/// `header` should come from a source the verifier trusts, such as a header
+/// chain it has validated; the proof only links the transaction to that header.
+pub fn verify_inclusion(proof: &InclusionProof, header: &BlockHeader) -> bool {
+    proof.header == *header && proof.branch.root(&proof.transaction.witness_id()) == Some(header.merkle_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(count: u8) -> Vec<Hash256> {
+        (0..count).map(|i| Hash256([i; 32])).collect()
+    }
+
+    #[test]
+    fn every_branch_leads_to_the_root() {
+        for count in 1..=9 {
+            let ids = ids(count);
+            let root = merkle_root(&ids);
+            for (index, id) in ids.iter().enumerate() {
+                let branch = MerkleBranch::new(&ids, index).unwrap();
+                assert_eq!(branch.root(id), Some(root));
+                assert_ne!(branch.root(&Hash256([0xff; 32])), Some(root));
+            }
+        }
+    }
+
+    #[test]
+    fn the_claimed_position_is_not_bound_to_the_root() {
+        let ids = ids(3);
+        let mut branch = MerkleBranch::new(&ids, 2).unwrap();
+        (branch.leaf_index, branch.leaf_count) = (1, 2);
+        assert_eq!(branch.root(&ids[2]), Some(merkle_root(&ids)));
+    }
+
+    #[test]
+    fn proofs_commit_to_the_signature() {
+        use crate::block::{compute_merkle_root, Block};
+        use crate::crypto::ed25519::SigningKey;
+        use crate::params::ChainParams;
+        use crate::pow::PowKind;
+        use crate::transaction::{Address, TxOutput};
+
+        let key = SigningKey::from_seed(&[7; 32]);
+        let output = TxOutput {
+            recipient: Address::of(&key),
+            amount: 5,
+        };
+        let transactions = vec![
+            Transaction::coinbase(1, vec![output]),
+            Transaction::new_signed(&key, vec![], vec![output], 1, 0),
+        ];
+        let mut header = Block::genesis(&ChainParams::new(PowKind::Sha256d, 0x207f_ffff)).header;
+        header.merkle_root = compute_merkle_root(&transactions);
+        let ids: Vec<Hash256> = transactions.iter().map(Transaction::witness_id).collect();
+        let mut proof = InclusionProof {
+            transaction: transactions[1].clone(),
+            header,
+            branch: MerkleBranch::new(&ids, 1).unwrap(),
+        };
+        assert!(verify_inclusion(&proof, &header));
+        // Same id, different signature bytes: the header does not vouch for them.
+        proof.transaction.signature[0] ^= 1;
+        assert_eq!(proof.transaction.id(), transactions[1].id());
+        assert!(!verify_inclusion(&proof, &header));
+    }
+}
@@ -0,0 +1,389 @@
+// Nonce search for new block headers, on one or several threads, with
+// cancellation, a deadline and progress reports
+
+use crate::block::BlockHeader;
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::pow::PowAlgorithm;
+use crate::target::Target;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Hashes a worker tries between checks for cancellation and the deadline.
+const BATCH: u64 = 64;
+
+/// Stops mining jobs from another thread, for example when a competing block
+/// arrives or the mempool changes.
+///
+/// Clones share the same jobs. Cancelling stops the jobs started so far, and jobs
+/// started afterwards run normally, so one handle serves every job of a `MinerConfig`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<Jobs>);
+
+/// Jobs are numbered from 1 as they start; every job up to `cancelled` is cancelled.
+#[derive(Debug, Default)]
+struct Jobs {
+    started: AtomicU64,
+    cancelled: AtomicU64,
+}
+
+impl CancelHandle {
+    /// Creates a handle that has not started any job.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every job started with this handle or a clone of it to stop; they fail
+    /// with `MiningCancelled`.
+    pub fn cancel(&self) {
+        let started = self.0.started.load(Ordering::Relaxed);
+        self.0.cancelled.fetch_max(started, Ordering::Relaxed);
+    }
+
+    /// Numbers a new job.
+    fn start(&self) -> u64 {
+        self.0.started.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Returns `true` once `cancel` has been called after job `job` started.
+    fn is_cancelled(&self, job: u64) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed) >= job
+    }
+}
+
+/// How far a mining job has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MiningProgress {
+    /// Hashes tried by all threads.
+    pub hashes: u64,
+    /// Time since the job started.
+    pub elapsed: Duration,
+    /// The lowest hash tried, if any.
+    pub best: Option<Hash256>,
+}
+
+impl MiningProgress {
+    /// Hashes per second since the job started.
+    pub fn hashrate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.hashes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// How blocks are mined.
+#[derive(Debug, Clone)]
+pub struct MinerConfig {
+    /// Number of threads to search on; the block found is the same whatever it is.
+    pub threads: usize,
+    /// Give up with `MiningDeadlineExceeded` once this instant has passed.
+    pub deadline: Option<Instant>,
+    /// Give up with `MiningCancelled` once this is cancelled while the job runs.
+    pub cancel: CancelHandle,
+    /// Receives a `MiningProgress` every `progress_interval` and once the job ends.
+    pub progress: Option<Sender<MiningProgress>>,
+    pub progress_interval: Duration,
+    /// Highest nonce tried before giving up with `NonceSpaceExhausted`, on which
+    /// `Blockchain::add_block` moves on to a new timestamp or extra nonce.
+    pub max_nonce: u32,
+}
+
+impl Default for MinerConfig {
+    fn default() -> Self {
+        MinerConfig {
+            threads: 1,
+            deadline: None,
+            cancel: CancelHandle::default(),
+            progress: None,
+            progress_interval: Duration::from_secs(1),
+            max_nonce: u32::MAX,
+        }
+    }
+}
+
+impl MinerConfig {
+    fn should_stop(&self, job: u64) -> bool {
+        self.cancel.is_cancelled(job) || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// What one worker's search ended with.
+enum Outcome {
+    /// The first valid nonce the worker tried.
+    Found(BlockHeader, Hash256),
+    /// The worker went past the lowest valid nonce found or ran out of nonces.
+    Done,
+    /// The job was cancelled or hit its deadline while the worker had nonces left.
+    Stopped,
+}
+
+/// The header being mined and what every try needs, prepared once per job.
+struct Work<'a, P: PowAlgorithm> {
+    pow: &'a P,
+    /// The job's number with `MinerConfig::cancel`.
+    job: u64,
+    header: BlockHeader,
+    midstate: P::Midstate,
+    target: [u8; 32],
+}
+
+/// Progress shared between the workers of one job.
+struct Shared {
+    /// The lowest valid nonce found so far, or `MinerConfig::max_nonce` until one is.
+    bound: AtomicU32,
+    hashes: AtomicU64,
+    best: Mutex<Option<Hash256>>,
+}
+
+impl Shared {
+    fn record(&self, hashes: u64, best: Option<Hash256>) {
+        self.hashes.fetch_add(hashes, Ordering::Relaxed);
+        if let Some(hash) = best {
+            let mut shared = self.best.lock().expect("mining progress lock poisoned");
+            if shared.is_none_or(|current| hash < current) {
+                *shared = Some(hash);
+            }
+        }
+    }
+
+    fn progress(&self, started: Instant) -> MiningProgress {
+        MiningProgress {
+            hashes: self.hashes.load(Ordering::Relaxed),
+            elapsed: started.elapsed(),
+            best: *self.best.lock().expect("mining progress lock poisoned"),
+        }
+    }
+}
+
+/// Finds the lowest nonce at which `header` meets its own target, returning the
+/// header with that nonce and its hash, or `NonceSpaceExhausted` if none up to
+/// `config.max_nonce` does; see `Blockchain::add_block` for what happens next.
+/// Fails with `BadBits` if the header's bits do not decode to a target.
+///
+/// With more than one thread, worker `i` of `n` tries nonces `i`, `i + n`, `i + 2n`
+/// and so on, and gives up once it is past the lowest valid nonce any worker has
+/// found. Every nonce below that one has been tried by the time all workers stop,
+/// so the result is the same whatever the number of threads. If the job is
+/// cancelled or runs past its deadline before that, it fails even if some worker
+/// found a valid nonce, since a lower one may have been missed.
+pub fn mine<P: PowAlgorithm>(
+    pow: &P,
+    header: BlockHeader,
+    config: &MinerConfig,
+) -> Result<(BlockHeader, Hash256), ChainError> {
+    let target = Target::from_compact(header.bits).ok_or(ChainError::BadBits {
+        height: header.height,
+        bits: header.bits,
+    })?;
+    let work = Work {
+        pow,
+        job: config.cancel.start(),
+        header,
+        midstate: pow.midstate(&header.encode()),
+        target: target.value().to_be_bytes(),
+    };
+    let threads = config.threads.clamp(1, u32::MAX as usize) as u32;
+    let started = Instant::now();
+    let shared = Shared {
+        bound: AtomicU32::new(config.max_nonce),
+        hashes: AtomicU64::new(0),
+        best: Mutex::new(None),
+    };
+    let caller = thread::current();
+    let finished = AtomicU32::new(0);
+    let outcomes: Vec<Outcome> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|first| {
+                let (work, shared, finished, caller) = (&work, &shared, &finished, caller.clone());
+                scope.spawn(move || {
+                    let outcome = search(work, first, threads, config, shared);
+                    finished.fetch_add(1, Ordering::Release);
+                    caller.unpark();
+                    outcome
+                })
+            })
+            .collect();
+        let interval = config.progress_interval.max(Duration::from_millis(1));
+        let mut next_report = started + interval;
+        while finished.load(Ordering::Acquire) < threads {
+            thread::park_timeout(next_report.saturating_duration_since(Instant::now()));
+            if Instant::now() >= next_report {
+                if let Some(progress) = &config.progress {
+                    // A receiver that has hung up just stops getting reports.
+                    let _ = progress.send(shared.progress(started));
+                }
+                next_report += interval;
+            }
+        }
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("mining thread panicked"))
+            .collect()
+    });
+    if let Some(progress) = &config.progress {
+        let _ = progress.send(shared.progress(started));
+    }
+
+    if outcomes.iter().any(|outcome| matches!(outcome, Outcome::Stopped)) {
+        return Err(if config.cancel.is_cancelled(work.job) {
+            ChainError::MiningCancelled { height: header.height }
+        } else {
+            ChainError::MiningDeadlineExceeded { height: header.height }
+        });
+    }
+    outcomes
+        .into_iter()
+        .filter_map(|outcome| match outcome {
+            Outcome::Found(header, hash) => Some((header, hash)),
+            _ => None,
+        })
+        .min_by_key(|(header, _)| header.nonce)
+        .ok_or(ChainError::NonceSpaceExhausted { height: header.height })
+}
+
+/// Tries nonces `first`, `first + step`, ... up to the shared bound, lowering it
+/// to the first valid one, and checks for cancellation between batches.
+///
+/// Each try only writes the nonce into the worker's own copy of the midstate,
+/// hashes from it and compares digest bytes, so the loop does not allocate, copy
+/// the header or decode the target.
+fn search<P: PowAlgorithm>(work: &Work<P>, first: u32, step: u32, config: &MinerConfig, shared: &Shared) -> Outcome {
+    let mut midstate = work.midstate.clone();
+    let mut nonce = Some(first);
+    loop {
+        if config.should_stop(work.job) {
+            return Outcome::Stopped;
+        }
+        let mut best: Option<Hash256> = None;
+        for tried in 1..=BATCH {
+            let Some(current) = nonce.filter(|nonce| *nonce <= shared.bound.load(Ordering::Relaxed)) else {
+                shared.record(tried - 1, best);
+                return Outcome::Done;
+            };
+            let hash = work.pow.hash_with_nonce(&mut midstate, current);
+            if best.is_none_or(|best| hash < best) {
+                best = Some(hash);
+            }
+            // Both are big-endian, so comparing the bytes compares the numbers.
+            if hash.0 <= work.target {
+                shared.bound.fetch_min(current, Ordering::Relaxed);
+                shared.record(tried, best);
+                return Outcome::Found(BlockHeader { nonce: current, ..work.header }, hash);
+            }
+            nonce = current.checked_add(step);
+        }
+        shared.record(BATCH, best);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::params::ChainParams;
+    use crate::pow::{Blake3, PowKind, Sha256d};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc;
+
+    fn mine_with_threads<P: PowAlgorithm>(pow: &P, header: BlockHeader) {
+        let results: Vec<(BlockHeader, Hash256)> = [1, 2, 3, 7]
+            .into_iter()
+            .map(|threads| {
+                let config = MinerConfig {
+                    threads,
+                    ..MinerConfig::default()
+                };
+                mine(pow, header, &config).unwrap()
+            })
+            .collect();
+        let (mined, hash) = results[0];
+        assert_eq!(pow.hash(&mined.encode()), hash);
+        assert!(Target::from_compact(header.bits).unwrap().is_met_by(&hash));
+        for result in &results {
+            assert_eq!(*result, (mined, hash));
+        }
+    }
+
+    #[test]
+    fn the_thread_count_does_not_change_the_block_found() {
+        let genesis = Block::genesis(&ChainParams::new(PowKind::Sha256d, 0x207f_ffff)).header;
+        // About one header in 65,536 meets this target, so the workers race over many batches.
+        let header = BlockHeader {
+            height: 1,
+            bits: 0x1f00_ffff,
+            ..genesis
+        };
+        mine_with_threads(&Sha256d, header);
+        mine_with_threads(&Blake3, header);
+    }
+
+    #[test]
+    fn cancelling_stops_only_the_jobs_already_started() {
+        let genesis = Block::genesis(&ChainParams::new(PowKind::Sha256d, 0x207f_ffff)).header;
+        let config = MinerConfig {
+            threads: 2,
+            ..MinerConfig::default()
+        };
+        // Nobody is mining yet, so this cancels nothing.
+        config.cancel.cancel();
+        let mined = mine(&Sha256d, BlockHeader { height: 1, ..genesis }, &config).unwrap();
+
+        // A target no nonce is likely to meet keeps the job running until it is cancelled.
+        let hard = BlockHeader {
+            height: 1,
+            bits: 0x0300_0001,
+            ..genesis
+        };
+        let (cancel, done) = (config.cancel.clone(), Arc::new(AtomicBool::new(false)));
+        let canceller = {
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(10));
+                    cancel.cancel();
+                }
+            })
+        };
+        assert_eq!(mine(&Sha256d, hard, &config), Err(ChainError::MiningCancelled { height: 1 }));
+        done.store(true, Ordering::Relaxed);
+        canceller.join().unwrap();
+
+        assert_eq!(mine(&Sha256d, BlockHeader { height: 1, ..genesis }, &config), Ok(mined));
+    }
+
+    #[test]
+    fn the_search_ends_at_the_last_nonce_or_on_undecodable_bits() {
+        let genesis = Block::genesis(&ChainParams::new(PowKind::Sha256d, 0x207f_ffff)).header;
+        let hard = BlockHeader {
+            height: 1,
+            bits: 0x0300_0001,
+            ..genesis
+        };
+        for threads in [1, 3] {
+            let (progress, reports) = mpsc::channel();
+            let config = MinerConfig {
+                threads,
+                progress: Some(progress),
+                max_nonce: 999,
+                ..MinerConfig::default()
+            };
+            assert_eq!(mine(&Sha256d, hard, &config), Err(ChainError::NonceSpaceExhausted { height: 1 }));
+            assert_eq!(reports.try_iter().last().unwrap().hashes, 1_000);
+        }
+
+        let negative = BlockHeader { bits: 0x0480_0001, ..hard };
+        assert_eq!(
+            mine(&Sha256d, negative, &MinerConfig::default()),
+            Err(ChainError::BadBits {
+                height: 1,
+                bits: 0x0480_0001,
+            })
+        );
+    }
+}
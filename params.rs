@@ -0,0 +1,163 @@
+// Consensus parameters shared by miners and validators
+
+use crate::pow::PowKind;
+use crate::retarget::Retarget;
+use crate::state::StateModel;
+use crate::transaction::TxOutput;
+use crate::versionbits::Deployment;
+
+/// Consensus rules a hard fork can change; see `ChainParams::rules_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    /// Proof-of-work algorithm blocks are hashed with.
+    pub pow: PowKind,
+    /// Largest allowed block size in bytes; see `Block::size`.
+    pub max_block_size: usize,
+    /// New coins the coinbase of block 1 would claim on top of the block's fees
+    /// under these rules; see `ChainParams::subsidy`.
+    pub initial_subsidy: u64,
+    /// Number of blocks after which the subsidy halves; 0 never halves it.
+    pub halving_interval: u32,
+}
+
+/// A hard fork: the block at `height` and every one after it follow `rules`.
+///
+/// Blocks before `height` keep the rules they were mined under, so the chain up
+/// to the fork still validates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upgrade {
+    pub name: &'static str,
+    pub height: u32,
+    pub rules: Rules,
+    /// Compact target the block at `height` carries, with retargeting starting
+    /// over from it as from genesis, for example when the algorithm changes.
+    /// `None` carries on retargeting as before.
+    pub bits: Option<u32>,
+}
+
+/// Parameters a chain is created with; every block must be checked against them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Rules in force from genesis until the first upgrade.
+    pub rules: Rules,
+    /// Hard forks scheduled in order of height; see `rules_at`.
+    pub upgrades: Vec<Upgrade>,
+    /// Proof-of-work target of the genesis block, in compact form. Blocks keep it
+    /// until the retargeting rule first adjusts it.
+    pub bits: u32,
+    /// Easiest target retargeting may reach, in compact form.
+    pub pow_limit: u32,
+    /// Desired average time between blocks, in seconds.
+    pub target_block_time: u64,
+    /// Seconds a block's timestamp may be ahead of the clock of the node checking it.
+    pub max_future_drift: u64,
+    /// Rule for adjusting the target to observed block times.
+    pub retarget: Retarget,
+    /// Whether the ledger tracks unspent outputs or account balances.
+    pub state_model: StateModel,
+    /// Outputs created by the genesis block, the chain's initial coin supply.
+    /// On an account chain each credits its recipient's balance.
+    pub genesis_outputs: Vec<TxOutput>,
+    /// Most coins that may ever be issued, counting the genesis allocations.
+    pub max_supply: u64,
+    /// Blocks a coinbase after genesis must wait before its outputs can be spent:
+    /// the rewards of block `h` are spendable from block `h + coinbase_maturity`,
+    /// so a reorg shallower than that cannot undo rewards that were spent.
+    pub coinbase_maturity: u32,
+    /// Soft forks miners signal for through header version bits, each on its own bit.
+    pub deployments: Vec<Deployment>,
+    /// Blocks in each version-bits signalling window; see `versionbits::state`.
+    pub signal_window: u32,
+    /// Signalling blocks within one window that lock a deployment in.
+    pub signal_threshold: u32,
+}
+
+impl ChainParams {
+    /// Parameters with ten-minute blocks retargeted every 2016 blocks, starting at
+    /// (and never easier than) the target encoded by `bits`, on the UTXO model with
+    /// no genesis allocations, blocks of up to 1 MB and a subsidy of 50 halving
+    /// every 210,000 blocks that matures after 100 blocks. No upgrades or deployments are
+    /// defined; those added lock in when 95% of a 2016-block window signals.
+    pub fn new(pow: PowKind, bits: u32) -> Self {
+        ChainParams {
+            rules: Rules {
+                pow,
+                max_block_size: 1_000_000,
+                initial_subsidy: 50,
+                halving_interval: 210_000,
+            },
+            upgrades: vec![],
+            bits,
+            pow_limit: bits,
+            target_block_time: 600,
+            max_future_drift: 2 * 60 * 60,
+            retarget: Retarget::Window { interval: 2016 },
+            state_model: StateModel::Utxo,
+            genesis_outputs: vec![],
+            max_supply: 21_000_000,
+            coinbase_maturity: 100,
+            deployments: vec![],
+            signal_window: 2016,
+            signal_threshold: 1916,
+        }
+    }
+
+    /// The rules the block at `height` must follow: those of the last upgrade at
+    /// or below it, or `rules` before the first.
+    pub fn rules_at(&self, height: u32) -> Rules {
+        self.upgrades
+            .iter()
+            .rev()
+            .find(|upgrade| upgrade.height <= height)
+            .map_or(self.rules, |upgrade| upgrade.rules)
+    }
+
+    /// Height and compact target of the last upgrade at or below `height` that
+    /// resets the target, if any.
+    pub fn last_target_reset(&self, height: u32) -> Option<(u32, u32)> {
+        self.upgrades
+            .iter()
+            .rev()
+            .filter(|upgrade| upgrade.height <= height)
+            .find_map(|upgrade| upgrade.bits.map(|bits| (upgrade.height, bits)))
+    }
+
+    /// New coins the coinbase of the block at `height` may claim under the rules
+    /// in force there: the initial subsidy halved once per halving interval
+    /// completed since block 1, and none for genesis.
+    pub fn subsidy(&self, height: u32) -> u64 {
+        if height == 0 {
+            return 0;
+        }
+        let rules = self.rules_at(height);
+        let halvings = (height - 1).checked_div(rules.halving_interval).unwrap_or(0);
+        rules.initial_subsidy.checked_shr(halvings).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsidy_halves_at_each_interval_until_it_runs_out() {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        assert_eq!(params.subsidy(0), 0);
+        assert_eq!(params.subsidy(1), 50);
+        assert_eq!(params.subsidy(210_000), 50);
+        assert_eq!(params.subsidy(210_001), 25);
+        assert_eq!(params.subsidy(420_000), 25);
+        assert_eq!(params.subsidy(420_001), 12);
+
+        params.rules.halving_interval = 10;
+        let subsidies: Vec<u64> = (0..8).map(|halvings| params.subsidy(halvings * 10 + 1)).collect();
+        assert_eq!(subsidies, [50, 25, 12, 6, 3, 1, 0, 0]);
+        assert_eq!(params.subsidy(60), 1);
+        // Past 63 halvings the shift would overflow; the subsidy stays at zero.
+        params.rules.halving_interval = 1;
+        assert_eq!(params.subsidy(u32::MAX), 0);
+        // Without an interval it never halves.
+        params.rules.halving_interval = 0;
+        assert_eq!(params.subsidy(u32::MAX), 50);
+    }
+}
@@ -0,0 +1,348 @@
+// Proof-of-work hash functions
+
+use crate::block::HEADER_LEN;
+use crate::crypto::blake3::{blake3, ChunkHasher};
+use crate::crypto::scrypt::scrypt;
+use crate::crypto::sha256::{sha256d, PaddedTail, Sha256};
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::params::ChainParams;
+use std::fmt;
+use std::iter;
+
+/// Identifies a proof-of-work algorithm in the chain parameters, with the cost
+/// parameters of those that have any, since they change every hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowKind {
+    Sha256d,
+    Blake3,
+    /// See `Scrypt::new`.
+    Scrypt { n: usize, r: usize, p: usize },
+}
+
+impl fmt::Display for PowKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowKind::Sha256d => f.write_str("sha256d"),
+            PowKind::Blake3 => f.write_str("blake3"),
+            PowKind::Scrypt { n, r, p } => write!(f, "scrypt(n={},r={},p={})", n, r, p),
+        }
+    }
+}
+
+/// A hash function that blocks are mined and validated against.
+///
+/// Implementations are shared between mining threads, hence `Sync`.
+pub trait PowAlgorithm: Clone + Sync {
+    /// What `midstate` precomputes for one header. Each mining thread hashes
+    /// with its own copy, which `hash_with_nonce` writes nonces into.
+    type Midstate: Clone + Sync;
+
+    /// The identifier recorded in the chain parameters.
+    fn kind(&self) -> PowKind;
+
+    /// Hashes the canonical header encoding into a 32-byte digest.
+    fn hash(&self, header: &[u8]) -> Hash256;
+
+    /// Does the part of hashing an encoded header that does not depend on its
+    /// nonce, the last four bytes.
+    fn midstate(&self, header: &[u8; HEADER_LEN]) -> Self::Midstate;
+
+    /// Hashes the header `midstate` was computed from with `nonce` in place of
+    /// its own, giving the same digest as `hash`. This is the mining hot loop, so
+    /// it only writes the four nonce bytes into `midstate` and hashes from there.
+    fn hash_with_nonce(&self, midstate: &mut Self::Midstate, nonce: u32) -> Hash256;
+
+    /// The algorithm of kind `kind`, for heights where a chain's schedule calls
+    /// for it, or `None` if this implementation cannot hash with it. A single
+    /// algorithm only provides itself; `AnyPow` provides every built-in one.
+    fn for_kind(&self, kind: PowKind) -> Option<Self> {
+        (self.kind() == kind).then(|| self.clone())
+    }
+}
+
+/// The algorithm `params` schedule for the block at `height`, taken from `pow`.
+pub fn at_height<P: PowAlgorithm>(pow: &P, params: &ChainParams, height: u32) -> Result<P, ChainError> {
+    let expected = params.rules_at(height).pow;
+    pow.for_kind(expected).ok_or(ChainError::PowAlgorithmMismatch {
+        expected,
+        actual: pow.kind(),
+    })
+}
+
+/// Checks that the upgrades in `params` are scheduled after genesis in order of
+/// height, and that `pow` provides every algorithm they schedule, with the same
+/// cost parameters, from genesis through the last upgrade.
+pub fn check_schedule<P: PowAlgorithm>(pow: &P, params: &ChainParams) -> Result<(), ChainError> {
+    let mut previous = 0;
+    for upgrade in &params.upgrades {
+        if upgrade.height <= previous {
+            return Err(ChainError::InvalidParams("upgrades must be scheduled after genesis in order of height"));
+        }
+        previous = upgrade.height;
+    }
+    let heights = iter::once(0).chain(params.upgrades.iter().map(|upgrade| upgrade.height));
+    for height in heights {
+        at_height(pow, params, height)?;
+    }
+    Ok(())
+}
+
+/// The two SHA-256 passes of a header, each padded ahead: the inner one after its
+/// first block, ending with the nonce, and the outer one over the inner digest.
+#[derive(Debug, Clone)]
+pub struct Sha256dMidstate {
+    inner: PaddedTail,
+    outer: PaddedTail,
+}
+
+/// Double SHA-256, as used by Bitcoin.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256d;
+
+impl PowAlgorithm for Sha256d {
+    type Midstate = Sha256dMidstate;
+
+    fn kind(&self) -> PowKind {
+        PowKind::Sha256d
+    }
+
+    fn hash(&self, header: &[u8]) -> Hash256 {
+        Hash256(sha256d(header))
+    }
+
+    /// Compresses the first 64-byte block and pads the rest, leaving the two
+    /// blocks of the inner hash and the one of the outer hash per nonce.
+    fn midstate(&self, header: &[u8; HEADER_LEN]) -> Self::Midstate {
+        let mut inner = Sha256::new();
+        inner.update(&header[..64]);
+        Sha256dMidstate {
+            inner: inner.padded_tail(&header[64..]),
+            outer: Sha256::new().padded_tail(&[0; 32]),
+        }
+    }
+
+    fn hash_with_nonce(&self, midstate: &mut Self::Midstate, nonce: u32) -> Hash256 {
+        midstate.inner.tail_mut(4).copy_from_slice(&nonce.to_le_bytes());
+        let inner = midstate.inner.finalize();
+        midstate.outer.tail_mut(32).copy_from_slice(&inner);
+        Hash256(midstate.outer.finalize())
+    }
+}
+
+/// BLAKE3 with its default 32-byte output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3;
+
+impl PowAlgorithm for Blake3 {
+    type Midstate = ChunkHasher;
+
+    fn kind(&self) -> PowKind {
+        PowKind::Blake3
+    }
+
+    fn hash(&self, header: &[u8]) -> Hash256 {
+        Hash256(blake3(header))
+    }
+
+    /// Absorbs the whole header, which fits in one chunk, into a `ChunkHasher`.
+    /// It compresses the first 64-byte block and keeps the last one, nonce
+    /// included, for `finalize` to compress once per nonce.
+    fn midstate(&self, header: &[u8; HEADER_LEN]) -> Self::Midstate {
+        let mut hasher = ChunkHasher::new();
+        hasher.update(header);
+        hasher
+    }
+
+    fn hash_with_nonce(&self, midstate: &mut Self::Midstate, nonce: u32) -> Hash256 {
+        midstate.tail_mut(4).copy_from_slice(&nonce.to_le_bytes());
+        Hash256(midstate.finalize())
+    }
+}
+
+/// Memory-hard scrypt with the header as both password and salt, as used by Litecoin.
+///
+/// Each hash touches `128 * r * n` bytes of memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Scrypt {
+    n: usize,
+    r: usize,
+    p: usize,
+}
+
+impl Scrypt {
+    /// Creates the algorithm with cost `n` (a power of two greater than one),
+    /// block size `r` and parallelism `p`.
+    pub fn new(n: usize, r: usize, p: usize) -> Result<Self, ChainError> {
+        if n < 2 || !n.is_power_of_two() {
+            return Err(ChainError::InvalidParams("scrypt cost must be a power of two greater than one"));
+        }
+        if r == 0 || p == 0 {
+            return Err(ChainError::InvalidParams("scrypt block size and parallelism must be positive"));
+        }
+        Ok(Scrypt { n, r, p })
+    }
+}
+
+impl Default for Scrypt {
+    fn default() -> Self {
+        Scrypt { n: 1024, r: 1, p: 1 }
+    }
+}
+
+impl PowAlgorithm for Scrypt {
+    type Midstate = [u8; HEADER_LEN];
+
+    fn kind(&self) -> PowKind {
+        PowKind::Scrypt {
+            n: self.n,
+            r: self.r,
+            p: self.p,
+        }
+    }
+
+    fn hash(&self, header: &[u8]) -> Hash256 {
+        let mut out = [0u8; 32];
+        scrypt(header, header, self.n, self.r, self.p, &mut out);
+        Hash256(out)
+    }
+
+    /// The whole header is the password and the salt, so nothing can be done
+    /// ahead; each hash also allocates its `128 * r * n` bytes of scratch memory.
+    fn midstate(&self, header: &[u8; HEADER_LEN]) -> Self::Midstate {
+        *header
+    }
+
+    fn hash_with_nonce(&self, midstate: &mut Self::Midstate, nonce: u32) -> Hash256 {
+        midstate[HEADER_LEN - 4..].copy_from_slice(&nonce.to_le_bytes());
+        self.hash(midstate)
+    }
+}
+
+/// Whichever built-in algorithm a chain's schedule calls for, for chains that
+/// switch algorithm in a hard fork; see `ChainParams::upgrades`.
+#[derive(Debug, Clone, Copy)]
+pub enum AnyPow {
+    Sha256d(Sha256d),
+    Blake3(Blake3),
+    Scrypt(Scrypt),
+}
+
+impl AnyPow {
+    /// Hashes with the algorithm `kind` names, failing if its scrypt parameters
+    /// are invalid; see `Scrypt::new`.
+    pub fn new(kind: PowKind) -> Result<Self, ChainError> {
+        Ok(match kind {
+            PowKind::Sha256d => AnyPow::Sha256d(Sha256d),
+            PowKind::Blake3 => AnyPow::Blake3(Blake3),
+            PowKind::Scrypt { n, r, p } => AnyPow::Scrypt(Scrypt::new(n, r, p)?),
+        })
+    }
+}
+
+/// The midstate of whichever algorithm an `AnyPow` hashes with. The SHA-256d one
+/// is boxed, as it is about three times the size of the others.
+#[derive(Clone)]
+pub enum AnyMidstate {
+    Sha256d(Box<<Sha256d as PowAlgorithm>::Midstate>),
+    Blake3(<Blake3 as PowAlgorithm>::Midstate),
+    Scrypt(<Scrypt as PowAlgorithm>::Midstate),
+}
+
+impl PowAlgorithm for AnyPow {
+    type Midstate = AnyMidstate;
+
+    fn kind(&self) -> PowKind {
+        match self {
+            AnyPow::Sha256d(pow) => pow.kind(),
+            AnyPow::Blake3(pow) => pow.kind(),
+            AnyPow::Scrypt(pow) => pow.kind(),
+        }
+    }
+
+    fn hash(&self, header: &[u8]) -> Hash256 {
+        match self {
+            AnyPow::Sha256d(pow) => pow.hash(header),
+            AnyPow::Blake3(pow) => pow.hash(header),
+            AnyPow::Scrypt(pow) => pow.hash(header),
+        }
+    }
+
+    fn midstate(&self, header: &[u8; HEADER_LEN]) -> Self::Midstate {
+        match self {
+            AnyPow::Sha256d(pow) => AnyMidstate::Sha256d(Box::new(pow.midstate(header))),
+            AnyPow::Blake3(pow) => AnyMidstate::Blake3(pow.midstate(header)),
+            AnyPow::Scrypt(pow) => AnyMidstate::Scrypt(pow.midstate(header)),
+        }
+    }
+
+    /// Panics if `midstate` was computed by a different algorithm than this one.
+    fn hash_with_nonce(&self, midstate: &mut Self::Midstate, nonce: u32) -> Hash256 {
+        match (self, midstate) {
+            (AnyPow::Sha256d(pow), AnyMidstate::Sha256d(midstate)) => pow.hash_with_nonce(midstate, nonce),
+            (AnyPow::Blake3(pow), AnyMidstate::Blake3(midstate)) => pow.hash_with_nonce(midstate, nonce),
+            (AnyPow::Scrypt(pow), AnyMidstate::Scrypt(midstate)) => pow.hash_with_nonce(midstate, nonce),
+            _ => panic!("midstate computed by a different algorithm"),
+        }
+    }
+
+    fn for_kind(&self, kind: PowKind) -> Option<Self> {
+        AnyPow::new(kind).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Upgrade;
+
+    #[test]
+    fn check_schedule_rejects_other_scrypt_costs() {
+        let params = ChainParams::new(Scrypt::default().kind(), 0x207f_ffff);
+        check_schedule(&Scrypt::default(), &params).unwrap();
+        let cheap = Scrypt::new(2, 1, 1).unwrap();
+        assert_eq!(
+            check_schedule(&cheap, &params),
+            Err(ChainError::PowAlgorithmMismatch {
+                expected: Scrypt::default().kind(),
+                actual: cheap.kind(),
+            })
+        );
+    }
+
+    #[test]
+    fn check_schedule_rejects_upgrades_at_genesis_or_out_of_order() {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        let upgrade = |height| Upgrade {
+            name: "fork",
+            height,
+            rules: params.rules,
+            bits: None,
+        };
+        let schedules = [vec![upgrade(0)], vec![upgrade(5), upgrade(3)], vec![upgrade(3), upgrade(3)]];
+        params.upgrades = vec![upgrade(3), upgrade(5)];
+        check_schedule(&Sha256d, &params).unwrap();
+        for upgrades in schedules {
+            params.upgrades = upgrades;
+            assert!(matches!(check_schedule(&Sha256d, &params), Err(ChainError::InvalidParams(_))));
+        }
+    }
+
+    #[test]
+    fn any_pow_hashes_like_the_algorithm_it_wraps() {
+        let header = [7u8; HEADER_LEN];
+        for kind in [PowKind::Sha256d, PowKind::Blake3, Scrypt::new(16, 1, 1).unwrap().kind()] {
+            let pow = AnyPow::new(kind).unwrap();
+            assert_eq!(pow.kind(), kind);
+            // The same midstate serves nonce after nonce.
+            let mut midstate = pow.midstate(&header);
+            for nonce in [9, 0, u32::MAX] {
+                let mut with_nonce = header;
+                with_nonce[HEADER_LEN - 4..].copy_from_slice(&nonce.to_le_bytes());
+                assert_eq!(pow.hash_with_nonce(&mut midstate, nonce), pow.hash(&with_nonce));
+            }
+        }
+        assert_eq!(AnyPow::new(PowKind::Sha256d).unwrap().hash(&header), Sha256d.hash(&header));
+        assert!(AnyPow::new(PowKind::Scrypt { n: 3, r: 1, p: 1 }).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+// Difficulty retargeting from observed block times
+
+use crate::block::BlockHeader;
+use crate::error::ChainError;
+use crate::params::ChainParams;
+use crate::target::Target;
+use crate::uint::U256;
+
+/// How the target moves from block to block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retarget {
+    /// Every block keeps the target of the previous one.
+    Fixed,
+    /// Bitcoin-style: every `interval` blocks the target is scaled by the ratio of
+    /// the observed to the expected time the last `interval` blocks took,
+    /// clamped to a factor of four in either direction.
+    Window { interval: u32 },
+    /// Linearly weighted moving average of the last `window` solve times, so recent
+    /// blocks count the most. Adjusts every block.
+    Lwma { window: u32 },
+    /// Absolutely scheduled exponential adjustment (aserti3-2d) anchored at block 1:
+    /// the target doubles or halves for every `half_life` seconds the chain runs
+    /// behind or ahead of schedule. Adjusts every block.
+    Asert { half_life: u64 },
+}
+
+/// Computes the compact target the block following `chain` must carry.
+///
+/// `chain` is every block or header up to and including the parent, starting
+/// with genesis. Genesis has a fixed timestamp, so its time never feeds into an
+/// adjustment.
+///
+/// An upgrade that resets the target sets the bits of its first block, and
+/// retargeting then starts over as if that block's parent were genesis.
+pub fn next_bits<H: AsRef<BlockHeader>>(params: &ChainParams, mut chain: &[H]) -> Result<u32, ChainError> {
+    let parent = chain.last().ok_or(ChainError::EmptyChain)?.as_ref();
+    let limit = pow_limit(params)?.value();
+    if let Some((reset_height, bits)) = params.last_target_reset(chain.len() as u32) {
+        if reset_height == chain.len() as u32 {
+            return Ok(bits);
+        }
+        chain = &chain[reset_height.saturating_sub(1) as usize..];
+    }
+    let height = chain.len() as u32;
+    let target = match params.retarget {
+        Retarget::Fixed => return Ok(parent.bits),
+        Retarget::Window { interval } => {
+            if interval < 2 || !height.is_multiple_of(interval) || height <= interval {
+                return Ok(parent.bits);
+            }
+            window(params, &chain[(height - interval) as usize..])
+        }
+        Retarget::Lwma { window } => {
+            if window == 0 || height <= window + 1 {
+                return Ok(parent.bits);
+            }
+            lwma(params, &chain[(height - window - 1) as usize..])
+        }
+        Retarget::Asert { half_life } => {
+            if height <= 1 {
+                return Ok(parent.bits);
+            }
+            asert(params, half_life, limit, chain[1].as_ref(), parent)
+        }
+    };
+    Ok(Target::new(target.min(limit)).to_compact())
+}
+
+fn pow_limit(params: &ChainParams) -> Result<Target, ChainError> {
+    Target::from_compact(params.pow_limit).ok_or(ChainError::InvalidParams("proof-of-work limit is not a valid compact target"))
+}
+
+fn target_of(header: &BlockHeader) -> U256 {
+    Target::from_compact(header.bits).map_or(U256::MAX, Target::value)
+}
+
+// Scales the parent target by observed / expected timespan over `blocks`.
+fn window<H: AsRef<BlockHeader>>(params: &ChainParams, blocks: &[H]) -> U256 {
+    let first = blocks[0].as_ref();
+    let last = blocks[blocks.len() - 1].as_ref();
+    let expected = params.target_block_time.saturating_mul(blocks.len() as u64 - 1);
+    let actual = last.timestamp.saturating_sub(first.timestamp).clamp(expected / 4, expected.saturating_mul(4));
+    scale(target_of(last), actual, expected)
+}
+
+// LWMA-1: the average target scaled by the weighted mean solve time over the
+// target block time. `blocks` holds `window + 1` blocks.
+fn lwma<H: AsRef<BlockHeader>>(params: &ChainParams, blocks: &[H]) -> U256 {
+    let n = blocks.len() as u64 - 1;
+    let spacing = params.target_block_time;
+    let mut weighted_time = 0u64;
+    let mut average_target = U256::ZERO;
+    for (i, pair) in blocks.windows(2).enumerate() {
+        // Out-of-order timestamps would make solve times negative; treat them as
+        // the shortest possible solve, and cap outliers at six block times.
+        let (previous, current) = (pair[0].as_ref(), pair[1].as_ref());
+        let solve_time = current.timestamp.saturating_sub(previous.timestamp).clamp(1, spacing.saturating_mul(6).max(1));
+        weighted_time = weighted_time.saturating_add((i as u64 + 1).saturating_mul(solve_time));
+        average_target = average_target + target_of(current).div_rem_u64(n).0;
+    }
+    let k = n * (n + 1) / 2;
+    // Don't let a burst of fast blocks raise the difficulty more than tenfold.
+    let expected = k.saturating_mul(spacing);
+    let weighted_time = weighted_time.max(expected / 10);
+    scale(average_target, weighted_time, expected)
+}
+
+// aserti3-2d: next = anchor * 2^((time_delta - spacing * height_delta) / half_life),
+// with the fractional power approximated by a cubic polynomial in 16.16 fixed point.
+fn asert(params: &ChainParams, half_life: u64, limit: U256, anchor: &BlockHeader, parent: &BlockHeader) -> U256 {
+    let time_delta = parent.timestamp as i128 - anchor.timestamp as i128;
+    let height_delta = parent.height as i128 - anchor.height as i128;
+    let exponent = (time_delta - params.target_block_time as i128 * height_delta) * 65536 / half_life.max(1) as i128;
+    let exponent = exponent.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+    let shifts = exponent >> 16;
+    let frac = (exponent & 0xffff) as u128;
+    let factor = 65536
+        + ((195_766_423_245_049 * frac + 971_821_376 * frac * frac + 5_127 * frac * frac * frac + (1 << 47)) >> 48);
+
+    // anchor * factor / 2^16, dropping the low bits first when the product would not fit.
+    let anchor_target = target_of(anchor);
+    let scaled = if anchor_target.bits() + 17 > 256 {
+        (anchor_target >> 16).checked_mul_u64(factor as u64)
+    } else {
+        anchor_target.checked_mul_u64(factor as u64).map(|product| product >> 16)
+    };
+    let Some(scaled) = scaled else {
+        return limit;
+    };
+    let next = if shifts < 0 {
+        scaled >> shifts.unsigned_abs().min(256) as u32
+    } else if scaled.bits() as i64 + shifts > 256 {
+        return limit;
+    } else {
+        scaled << shifts as u32
+    };
+    if next.is_zero() { U256::ONE } else { next }
+}
+
+// Computes target * numerator / denominator exactly, saturating at the maximum
+// value only when the result does not fit. Near the easiest targets the product
+// itself overflows, so it is taken as (target / d) * n + (target % d) * n / d.
+fn scale(target: U256, numerator: u64, denominator: u64) -> U256 {
+    let denominator = denominator.max(1);
+    let (quotient, remainder) = target.div_rem_u64(denominator);
+    let low = (remainder as u128 * numerator as u128 / denominator as u128) as u64;
+    quotient
+        .checked_mul_u64(numerator)
+        .and_then(|high| high.checked_add(U256::from(low)))
+        .unwrap_or(U256::MAX)
+}
+
+/// Checks that `header` claims the target retargeting expects after `chain`,
+/// every block or header up to and including its parent.
+pub fn check_bits<H: AsRef<BlockHeader>>(params: &ChainParams, chain: &[H], header: &BlockHeader) -> Result<(), ChainError> {
+    let expected = next_bits(params, chain)?;
+    if header.bits != expected {
+        return Err(ChainError::BadDifficulty {
+            height: header.height,
+            expected,
+            actual: header.bits,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::Hash256;
+    use crate::pow::PowKind;
+
+    /// Genesis plus `blocks` headers all stamped `timestamp`, each carrying the bits
+    /// `next_bits` asks for.
+    fn same_second_chain(params: &ChainParams, blocks: u32, timestamp: u64) -> Vec<BlockHeader> {
+        let mut chain = vec![BlockHeader {
+            version: 0,
+            height: 0,
+            previous_hash: Hash256::ZERO,
+            merkle_root: Hash256::ZERO,
+            state_root: Hash256::ZERO,
+            timestamp: 0,
+            bits: params.bits,
+            nonce: 0,
+        }];
+        for height in 1..=blocks {
+            let bits = next_bits(params, &chain).unwrap();
+            chain.push(BlockHeader {
+                height,
+                timestamp,
+                bits,
+                ..chain[0]
+            });
+        }
+        chain
+    }
+
+    #[test]
+    fn fast_blocks_raise_the_difficulty_even_at_the_easiest_target() {
+        for retarget in [Retarget::Window { interval: 2 }, Retarget::Lwma { window: 3 }] {
+            let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+            params.retarget = retarget;
+            let chain = same_second_chain(&params, 8, 1_000);
+            let last = Target::from_compact(chain[8].bits).unwrap().value();
+            assert!(last < Target::from_compact(0x207f_ffff).unwrap().value(), "{:?} never moved", retarget);
+        }
+    }
+
+    /// Genesis plus `blocks` headers `solve_time` seconds apart, each carrying the
+    /// bits `next_bits` asks for.
+    fn spaced_chain(params: &ChainParams, blocks: u32, solve_time: u64) -> Vec<BlockHeader> {
+        let mut chain = same_second_chain(params, 0, 0);
+        for height in 1..=blocks {
+            let bits = next_bits(params, &chain).unwrap();
+            chain.push(BlockHeader {
+                height,
+                timestamp: height as u64 * solve_time,
+                bits,
+                ..chain[0]
+            });
+        }
+        chain
+    }
+
+    /// Parameters starting at Bitcoin's initial target, which may get 256 times easier.
+    fn params_with(retarget: Retarget) -> ChainParams {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x1d00_ffff);
+        params.pow_limit = 0x2000_ffff;
+        params.retarget = retarget;
+        params
+    }
+
+    fn target(bits: u32) -> U256 {
+        Target::from_compact(bits).unwrap().value()
+    }
+
+    #[test]
+    fn lwma_follows_the_solve_times() {
+        let params = params_with(Retarget::Lwma { window: 10 });
+        let initial = target(params.bits);
+        // On schedule the target stays put, but for the rounding of the average.
+        let on_schedule = target(next_bits(&params, &spaced_chain(&params, 30, 600)).unwrap());
+        assert!(on_schedule <= initial && on_schedule >= initial.div_rem_u64(1000).0.checked_mul_u64(999).unwrap());
+        let slow = target(next_bits(&params, &spaced_chain(&params, 30, 900)).unwrap());
+        assert!(slow > initial.checked_mul_u64(3).unwrap() >> 1);
+        let fast = target(next_bits(&params, &spaced_chain(&params, 30, 300)).unwrap());
+        assert!(fast < initial.checked_mul_u64(2).unwrap() / U256::from(3));
+        // Very slow blocks only make it as easy as the limit.
+        let stalled = next_bits(&params, &spaced_chain(&params, 60, 86_400)).unwrap();
+        assert_eq!(stalled, params.pow_limit);
+    }
+
+    #[test]
+    fn asert_doubles_the_target_per_half_life_behind_schedule() {
+        let params = params_with(Retarget::Asert { half_life: 3_600 });
+        let initial = Target::from_compact(params.bits).unwrap();
+        // Block 1 is the anchor. Seven blocks in, six solve times have passed since it.
+        assert_eq!(next_bits(&params, &spaced_chain(&params, 7, 600)).unwrap(), params.bits);
+        // 600 seconds late six times over is one half-life behind.
+        let behind = next_bits(&params, &spaced_chain(&params, 7, 1_200)).unwrap();
+        assert_eq!(behind, Target::new(initial.value() << 1).to_compact());
+        // Blocks in the same second as the anchor are one half-life ahead.
+        let mut ahead = spaced_chain(&params, 1, 600);
+        for height in 2..=7 {
+            let bits = next_bits(&params, &ahead).unwrap();
+            ahead.push(BlockHeader { height, bits, ..ahead[1] });
+        }
+        let ahead = next_bits(&params, &ahead).unwrap();
+        assert_eq!(ahead, Target::new(initial.value() >> 1).to_compact());
+        // Between whole half-lives the target moves smoothly.
+        let between = target(next_bits(&params, &spaced_chain(&params, 7, 900)).unwrap());
+        assert!(between > initial.value() && between < initial.value() << 1);
+    }
+
+    #[test]
+    fn huge_block_times_do_not_overflow() {
+        for retarget in [Retarget::Window { interval: 4 }, Retarget::Lwma { window: 4 }] {
+            let mut params = params_with(retarget);
+            params.target_block_time = u64::MAX / 2;
+            // Any real spacing is far ahead of such a schedule, so the difficulty only rises.
+            for solve_time in [1, 1 << 60] {
+                let next = next_bits(&params, &spaced_chain(&params, 9, solve_time)).unwrap();
+                assert!(target(next) < target(params.bits), "{:?} at {}", retarget, solve_time);
+            }
+        }
+    }
+
+    #[test]
+    fn scale_is_exact_when_the_product_overflows() {
+        let target = U256::MAX >> 1;
+        assert_eq!(scale(target, 4, 4), target);
+        assert_eq!(scale(target, 1, 4), target >> 2);
+        assert_eq!(scale(target, 3, 1), U256::MAX);
+        assert_eq!(scale(U256::from(10), 7, 3), U256::from(23));
+    }
+}
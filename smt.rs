@@ -0,0 +1,178 @@
+// Sparse Merkle tree committing to the ledger state, updated entry by entry
+
+use crate::crypto::sha256::sha256d;
+use crate::hash::Hash256;
+use crate::merkle::{leaf_hash, node_hash};
+use std::collections::{BTreeMap, HashMap};
+
+/// A Merkle tree over 256-bit keys in which every key has a fixed place: the
+/// path from the root follows the key's bits, most significant first.
+///
+/// An empty subtree hashes to zero and a subtree holding a single entry hashes
+/// to that entry's leaf, so only the nodes above two or more entries are stored.
+/// Changing an entry rehashes the stored nodes on its path, about `log2(len)` of
+/// them, and the root depends only on the entries, not on the order of changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SparseMerkleTree {
+    /// The leaf of every entry, by key.
+    leaves: BTreeMap<Hash256, Hash256>,
+    /// The hash of every node above two or more entries, by depth and key prefix.
+    nodes: HashMap<(u16, Hash256), Hash256>,
+}
+
+impl SparseMerkleTree {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Commits to every entry. An empty tree has the all-zero root.
+    pub fn root(&self) -> Hash256 {
+        self.subtree_hash(0, &Hash256::ZERO)
+    }
+
+    /// Sets the entry at `key` to `value`, the hash of whatever it stands for.
+    pub fn insert(&mut self, key: Hash256, value: Hash256) {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(key.as_bytes());
+        bytes[32..].copy_from_slice(value.as_bytes());
+        self.leaves.insert(key, leaf_hash(&Hash256(sha256d(&bytes))));
+        let depth = self.shared_depth(&key);
+        self.rehash(&key, depth);
+    }
+
+    /// Removes the entry at `key`, if any.
+    pub fn remove(&mut self, key: &Hash256) {
+        if self.leaves.contains_key(key) {
+            let depth = self.shared_depth(key);
+            self.leaves.remove(key);
+            self.rehash(key, depth);
+        }
+    }
+
+    /// Number of levels from the root down along `key`'s path whose subtree
+    /// holds two or more entries, and so may have a stored node.
+    fn shared_depth(&self, key: &Hash256) -> u16 {
+        let mut depth = 0;
+        while depth < 256 && self.entries_under(depth, &prefix(key, depth)) >= 2 {
+            depth += 1;
+        }
+        depth
+    }
+
+    /// Recomputes the stored nodes on `key`'s path above `depth`, deepest first.
+    fn rehash(&mut self, key: &Hash256, depth: u16) {
+        for depth in (0..depth).rev() {
+            let left = prefix(key, depth);
+            if self.entries_under(depth, &left) < 2 {
+                self.nodes.remove(&(depth, left));
+                continue;
+            }
+            let right = with_bit(&left, depth);
+            let hash = node_hash(&self.subtree_hash(depth + 1, &left), &self.subtree_hash(depth + 1, &right));
+            self.nodes.insert((depth, left), hash);
+        }
+    }
+
+    fn subtree_hash(&self, depth: u16, prefix: &Hash256) -> Hash256 {
+        let mut entries = self.leaves.range(*prefix..=last_key(prefix, depth));
+        match (entries.next(), entries.next()) {
+            (None, _) => Hash256::ZERO,
+            (Some((_, leaf)), None) => *leaf,
+            _ => self.nodes[&(depth, *prefix)],
+        }
+    }
+
+    /// Counts the entries under the node at `depth` with key prefix `prefix`, up to two.
+    fn entries_under(&self, depth: u16, prefix: &Hash256) -> usize {
+        self.leaves.range(*prefix..=last_key(prefix, depth)).take(2).count()
+    }
+}
+
+/// `key` with every bit from `depth` on cleared.
+fn prefix(key: &Hash256, depth: u16) -> Hash256 {
+    let mut bytes = *key.as_bytes();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let kept = (depth as usize).saturating_sub(8 * i).min(8);
+        *byte &= !(0xffu16 >> kept) as u8;
+    }
+    Hash256(bytes)
+}
+
+/// The greatest key starting with `prefix`: every bit from `depth` on set.
+fn last_key(prefix: &Hash256, depth: u16) -> Hash256 {
+    let mut bytes = *prefix.as_bytes();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let kept = (depth as usize).saturating_sub(8 * i).min(8);
+        *byte |= (0xffu16 >> kept) as u8;
+    }
+    Hash256(bytes)
+}
+
+/// `prefix` with the bit at `depth` set.
+fn with_bit(prefix: &Hash256, depth: u16) -> Hash256 {
+    let mut bytes = *prefix.as_bytes();
+    bytes[depth as usize / 8] |= 0x80 >> (depth % 8);
+    Hash256(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: u32) -> Hash256 {
+        Hash256(sha256d(&i.to_le_bytes()))
+    }
+
+    /// The root computed from scratch over the leaves, to check the stored nodes against.
+    fn full_root(tree: &SparseMerkleTree, depth: u16, prefix: &Hash256) -> Hash256 {
+        let mut entries = tree.leaves.range(*prefix..=last_key(prefix, depth));
+        match (entries.next(), entries.next()) {
+            (None, _) => Hash256::ZERO,
+            (Some((_, leaf)), None) => *leaf,
+            _ => node_hash(
+                &full_root(tree, depth + 1, prefix),
+                &full_root(tree, depth + 1, &with_bit(prefix, depth)),
+            ),
+        }
+    }
+
+    #[test]
+    fn incremental_root_matches_a_full_recomputation() {
+        let mut tree = SparseMerkleTree::new();
+        assert_eq!(tree.root(), Hash256::ZERO);
+        let mut roots = vec![tree.root()];
+        for i in 0..64 {
+            tree.insert(key(i), key(i + 1000));
+            assert_eq!(tree.root(), full_root(&tree, 0, &Hash256::ZERO));
+            roots.push(tree.root());
+        }
+        tree.insert(key(7), key(7));
+        assert_eq!(tree.root(), full_root(&tree, 0, &Hash256::ZERO));
+        tree.insert(key(7), key(1007));
+        assert_eq!(tree.root(), roots[64]);
+        for i in (0..64).rev() {
+            tree.remove(&key(i));
+            assert_eq!(tree.root(), roots[i as usize]);
+        }
+        assert_eq!(tree, SparseMerkleTree::new());
+    }
+
+    #[test]
+    fn root_does_not_depend_on_insertion_order() {
+        let mut forward = SparseMerkleTree::new();
+        let mut backward = SparseMerkleTree::new();
+        for i in 0..32 {
+            forward.insert(key(i), key(i));
+            backward.insert(key(31 - i), key(31 - i));
+        }
+        assert_eq!(forward, backward);
+        // Keys sharing a long prefix only add stored nodes down to where they part.
+        let mut close = key(0);
+        close.0[31] ^= 1;
+        forward.insert(close, key(0));
+        assert_eq!(forward.root(), full_root(&forward, 0, &Hash256::ZERO));
+        forward.remove(&close);
+        assert_eq!(forward, backward);
+    }
+}
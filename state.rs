@@ -0,0 +1,105 @@
+// Ledger state built by applying blocks, under the model a chain was created with
+
+use crate::account::{Account, AccountState, AccountUndo};
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::params::ChainParams;
+use crate::transaction::{Address, Transaction};
+use crate::utxo::{BlockUndo, UtxoSet};
+
+/// How a chain tracks who owns what.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StateModel {
+    /// Coins: transactions spend earlier outputs; see `UtxoSet`.
+    #[default]
+    Utxo,
+    /// Accounts: transactions debit the sender's balance; see `AccountState`.
+    Account,
+}
+
+/// The ledger state after some prefix of the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainState {
+    Utxo(UtxoSet),
+    Account(AccountState),
+}
+
+/// What connecting a block changed; see `ChainState::disconnect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateUndo {
+    Utxo(BlockUndo),
+    Account(AccountUndo),
+}
+
+impl ChainState {
+    /// Creates an empty state in the model and with the coinbase maturity `params` name.
+    pub fn new(params: &ChainParams) -> Self {
+        match params.state_model {
+            StateModel::Utxo => ChainState::Utxo(UtxoSet::new(params.coinbase_maturity)),
+            StateModel::Account => ChainState::Account(AccountState::new(params.coinbase_maturity)),
+        }
+    }
+
+    /// The model this state follows.
+    pub fn model(&self) -> StateModel {
+        match self {
+            ChainState::Utxo(_) => StateModel::Utxo,
+            ChainState::Account(_) => StateModel::Account,
+        }
+    }
+
+    /// Applies the transactions of the block at `height` in order. On error the
+    /// state is left unchanged.
+    pub fn connect(&mut self, height: u32, transactions: &[Transaction]) -> Result<StateUndo, ChainError> {
+        match self {
+            ChainState::Utxo(utxos) => utxos.connect(height, transactions).map(StateUndo::Utxo),
+            ChainState::Account(accounts) => accounts.connect(height, transactions).map(StateUndo::Account),
+        }
+    }
+
+    /// Reverts a block applied by `connect`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `undo` was produced by a state of the other model.
+    pub fn disconnect(&mut self, undo: StateUndo) {
+        match (self, undo) {
+            (ChainState::Utxo(utxos), StateUndo::Utxo(undo)) => utxos.disconnect(undo),
+            (ChainState::Account(accounts), StateUndo::Account(undo)) => accounts.disconnect(undo),
+            _ => panic!("undo record does not match the state model"),
+        }
+    }
+
+    /// Commits to the whole state; block headers carry it as their state root.
+    pub fn state_root(&self) -> Hash256 {
+        match self {
+            ChainState::Utxo(utxos) => utxos.state_root(),
+            ChainState::Account(accounts) => accounts.state_root(),
+        }
+    }
+
+    /// Coins `address` may spend in a block at `height`: its mature unspent
+    /// outputs or its account balance plus the rewards matured by then.
+    pub fn balance(&self, address: &Address, height: u32) -> u64 {
+        match self {
+            ChainState::Utxo(utxos) => utxos.balance(address, height),
+            ChainState::Account(accounts) => accounts.balance(address, height),
+        }
+    }
+
+    /// The unspent output set, if the chain uses coins.
+    pub fn utxos(&self) -> Option<&UtxoSet> {
+        match self {
+            ChainState::Utxo(utxos) => Some(utxos),
+            ChainState::Account(_) => None,
+        }
+    }
+
+    /// The account at `address`, if the chain uses accounts.
+    pub fn account(&self, address: &Address) -> Option<Account> {
+        match self {
+            ChainState::Utxo(_) => None,
+            ChainState::Account(accounts) => Some(accounts.get(address)),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Proof-of-work targets and their compact "bits" encoding
+
+use crate::hash::Hash256;
+use crate::uint::U256;
+use std::fmt;
+
+/// The largest value a block hash may have, read as a big-endian 256-bit integer.
+///
+/// A lower target means more work: on average `2^256 / (target + 1)` hashes
+/// are needed to find a block that meets it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Target(U256);
+
+impl Target {
+    pub fn new(value: U256) -> Self {
+        Target(value)
+    }
+
+    pub fn value(self) -> U256 {
+        self.0
+    }
+
+    /// Decodes the compact form stored in block headers.
+    ///
+    /// The top byte is a base-256 exponent and the low 23 bits a mantissa, so
+    /// the value is `mantissa * 256^(exponent - 3)`. Returns `None` for zero,
+    /// negative (sign bit `0x00800000` set) or overflowing encodings.
+    pub fn from_compact(bits: u32) -> Option<Target> {
+        let exponent = bits >> 24;
+        let mantissa = bits & 0x007f_ffff;
+        if bits & 0x0080_0000 != 0 && mantissa != 0 {
+            return None;
+        }
+        let value = if exponent <= 3 {
+            U256::from((mantissa >> (8 * (3 - exponent))) as u64)
+        } else {
+            let value = U256::from(mantissa as u64);
+            if exponent > 34 || value.bits() + 8 * (exponent - 3) > 256 {
+                return None;
+            }
+            value << (8 * (exponent - 3))
+        };
+        (!value.is_zero()).then_some(Target(value))
+    }
+
+    /// Encodes the target in compact form, truncating it to a 23-bit mantissa.
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.0.bits().div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            (self.0.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (self.0 >> (8 * (size - 3))).low_u64() as u32
+        };
+        // The mantissa is signed; move a set top bit into the exponent.
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        mantissa | (size << 24)
+    }
+
+    /// Checks whether a block hash meets this target.
+    pub fn is_met_by(self, hash: &Hash256) -> bool {
+        U256::from_be_bytes(hash.0) <= self.0
+    }
+
+    /// Expected number of hashes needed to meet this target, `2^256 / (target + 1)`.
+    pub fn work(self) -> U256 {
+        // 2^256 itself does not fit, so compute (2^256 - target - 1) / (target + 1) + 1.
+        match self.0.checked_add(U256::ONE) {
+            Some(divisor) => (!self.0 / divisor) + U256::ONE,
+            None => U256::ONE,
+        }
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Target({:?})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_round_trips() {
+        for bits in [0x1d00ffff, 0x207fffff, 0x1f00ffff, 0x05009234, 0x02123400, 0x01120000, 0x20010000] {
+            assert_eq!(Target::from_compact(bits).unwrap().to_compact(), bits, "{:#010x}", bits);
+        }
+        assert_eq!(Target::from_compact(0x1d00ffff).unwrap().value(), U256::from(0xffff) << 208);
+        // Mantissa bytes below the exponent are dropped, and re-encoding normalizes.
+        assert_eq!(Target::from_compact(0x02123456).unwrap().to_compact(), 0x02123400);
+        assert_eq!(Target::from_compact(0x04000080).unwrap().to_compact(), 0x03008000);
+        assert_eq!(Target::from_compact(0x22000001).unwrap().to_compact(), 0x20010000);
+    }
+
+    #[test]
+    fn rejects_zero_negative_and_overflowing_encodings() {
+        for bits in [0, 0x01003456, 0x00800000, 0x04923456, 0x01fedcba, 0x21010000, 0x23000001, 0xff123456] {
+            assert_eq!(Target::from_compact(bits), None, "{:#010x}", bits);
+        }
+    }
+
+    #[test]
+    fn work_of_known_targets() {
+        // Bitcoin's genesis target takes 2^32 + 2^16 + 1 hashes, regtest's two.
+        assert_eq!(Target::from_compact(0x1d00ffff).unwrap().work(), U256::from(0x1_0001_0001));
+        assert_eq!(Target::from_compact(0x207fffff).unwrap().work(), U256::from(2));
+        assert_eq!(Target::new(U256::MAX).work(), U256::ONE);
+        assert_eq!(Target::new(U256::ONE).work(), U256::ONE << 255);
+    }
+}
@@ -0,0 +1,197 @@
+// Signed transactions carried in block bodies
+
+use crate::crypto::ed25519::{self, SigningKey};
+use crate::crypto::sha256::sha256d;
+use crate::hash::Hash256;
+use std::fmt;
+
+/// An account or coin owner, identified by its Ed25519 public key.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Address(pub [u8; 32]);
+
+impl Address {
+    /// The address controlled by `key`.
+    pub fn of(key: &SigningKey) -> Self {
+        Address(key.public_key())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address({})", self)
+    }
+}
+
+/// Refers to an output of an earlier transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OutPoint {
+    pub tx_id: Hash256,
+    pub index: u32,
+}
+
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.tx_id, self.index)
+    }
+}
+
+/// Pays `amount` to `recipient`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TxOutput {
+    pub recipient: Address,
+    pub amount: u64,
+}
+
+/// A transfer authorized by the sender's Ed25519 signature.
+///
+/// On a UTXO chain `inputs` name earlier outputs owned by the sender that the
+/// transaction spends; they must add up exactly to the outputs plus `fee`, which
+/// goes to the miner. On an account chain `inputs` is empty, the outputs and fee
+/// are debited from the sender's balance and `nonce` must be the sender's next
+/// nonce. Either way the nonce keeps otherwise identical transactions distinct.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Transaction {
+    pub sender: Address,
+    pub inputs: Vec<OutPoint>,
+    pub outputs: Vec<TxOutput>,
+    pub fee: u64,
+    pub nonce: u64,
+    /// Opts in to replace-by-fee: the mempool may swap the transaction for a
+    /// conflicting one that pays more. Has no effect on validity.
+    pub replaceable: bool,
+    pub signature: [u8; 64],
+}
+
+impl Transaction {
+    /// The coinbase of the block at `height`: an unsigned transaction from the zero
+    /// address that spends nothing and creates `outputs`. Its nonce is the height,
+    /// so coinbases paying the same outputs in different blocks have distinct ids.
+    ///
+    /// The genesis allocations are the coinbase of block 0.
+    pub fn coinbase(height: u32, outputs: Vec<TxOutput>) -> Self {
+        Self::coinbase_with_extra_nonce(height, 0, outputs)
+    }
+
+    /// A coinbase like `coinbase(height, outputs)` whose nonce also carries
+    /// `extra_nonce` in its upper 32 bits. Miners change it to get a new Merkle
+    /// root once every header nonce has failed.
+    pub fn coinbase_with_extra_nonce(height: u32, extra_nonce: u32, outputs: Vec<TxOutput>) -> Self {
+        Transaction {
+            sender: Address::default(),
+            inputs: vec![],
+            outputs,
+            fee: 0,
+            nonce: (extra_nonce as u64) << 32 | height as u64,
+            replaceable: false,
+            signature: [0; 64],
+        }
+    }
+
+    /// Returns `true` if the transaction has the shape of a coinbase. Only the
+    /// first transaction of a block may.
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == Address::default() && self.inputs.is_empty() && self.signature == [0; 64]
+    }
+
+    /// Builds a transaction from `key`'s address and signs it.
+    pub fn new_signed(key: &SigningKey, inputs: Vec<OutPoint>, outputs: Vec<TxOutput>, fee: u64, nonce: u64) -> Self {
+        Self::sign(key, inputs, outputs, fee, nonce, false)
+    }
+
+    /// Like `new_signed`, but opts in to replace-by-fee; see `Mempool::insert`.
+    pub fn new_replaceable(
+        key: &SigningKey,
+        inputs: Vec<OutPoint>,
+        outputs: Vec<TxOutput>,
+        fee: u64,
+        nonce: u64,
+    ) -> Self {
+        Self::sign(key, inputs, outputs, fee, nonce, true)
+    }
+
+    fn sign(
+        key: &SigningKey,
+        inputs: Vec<OutPoint>,
+        outputs: Vec<TxOutput>,
+        fee: u64,
+        nonce: u64,
+        replaceable: bool,
+    ) -> Self {
+        let mut transaction = Transaction {
+            sender: Address::of(key),
+            inputs,
+            outputs,
+            fee,
+            nonce,
+            replaceable,
+            signature: [0; 64],
+        };
+        transaction.signature = key.sign(&transaction.signing_bytes());
+        transaction
+    }
+
+    /// Serializes every field except the signature into the canonical byte string
+    /// that is signed and hashed.
+    ///
+    /// Integers are little-endian, lists are prefixed with their length as a `u32`
+    /// and the replaceable flag is a single byte.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(57 + 36 * self.inputs.len() + 40 * self.outputs.len());
+        bytes.extend_from_slice(&self.sender.0);
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            bytes.extend_from_slice(input.tx_id.as_bytes());
+            bytes.extend_from_slice(&input.index.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.outputs.len() as u32).to_le_bytes());
+        for output in &self.outputs {
+            bytes.extend_from_slice(&output.recipient.0);
+            bytes.extend_from_slice(&output.amount.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.fee.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.push(self.replaceable as u8);
+        bytes
+    }
+
+    /// Size in bytes of the transaction's encoding: its signing bytes followed by
+    /// the signature. Block size limits and fee rates are measured in it.
+    pub fn size(&self) -> usize {
+        57 + 36 * self.inputs.len() + 40 * self.outputs.len() + 64
+    }
+
+    /// The transaction id: the double SHA-256 of the signing bytes.
+    ///
+    /// The signature is left out so that the id is fixed before signing and
+    /// cannot be changed by re-encoding the signature.
+    pub fn id(&self) -> Hash256 {
+        Hash256(sha256d(&self.signing_bytes()))
+    }
+
+    /// The witness id: the double SHA-256 of the signing bytes followed by the
+    /// signature. Blocks commit to it, so a header fixes the signatures too.
+    pub fn witness_id(&self) -> Hash256 {
+        let mut bytes = self.signing_bytes();
+        bytes.extend_from_slice(&self.signature);
+        Hash256(sha256d(&bytes))
+    }
+
+    /// Checks that the sender signed exactly these contents.
+    pub fn verify_signature(&self) -> bool {
+        ed25519::verify(&self.sender.0, &self.signing_bytes(), &self.signature)
+    }
+
+    /// Sum of all output amounts, or `None` if it overflows.
+    pub fn total_output(&self) -> Option<u64> {
+        self.outputs.iter().try_fold(0u64, |total, output| total.checked_add(output.amount))
+    }
+}
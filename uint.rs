@@ -0,0 +1,298 @@
+// Unsigned 256-bit integer arithmetic for targets and chain work
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Not, Shl, Shr, Sub};
+
+/// A 256-bit unsigned integer stored as four little-endian `u64` limbs.
+///
+/// Arithmetic panics on overflow and underflow like the primitive integers do
+/// in debug builds; use the `checked_*` methods where the input is untrusted.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    /// Interprets 32 bytes as a big-endian integer.
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, chunk) in bytes.chunks_exact(8).enumerate() {
+            limbs[3 - i] = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    /// Returns the big-endian byte representation.
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, chunk) in bytes.chunks_exact_mut(8).enumerate() {
+            chunk.copy_from_slice(&self.0[3 - i].to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Returns the least significant 64 bits.
+    pub fn low_u64(self) -> u64 {
+        self.0[0]
+    }
+
+    pub fn is_zero(self) -> bool {
+        self == U256::ZERO
+    }
+
+    /// Number of bits needed to represent the value; zero for zero.
+    pub fn bits(self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + 64 - self.0[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let mut out = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in out.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        (!carry).then_some(U256(out))
+    }
+
+    pub fn checked_sub(self, other: U256) -> Option<U256> {
+        let mut out = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in out.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        (!borrow).then_some(U256(out))
+    }
+
+    /// Multiplies by a `u64`, returning `None` on overflow.
+    pub fn checked_mul_u64(self, factor: u64) -> Option<U256> {
+        let mut out = [0u64; 4];
+        let mut carry = 0u128;
+        for (i, limb) in out.iter_mut().enumerate() {
+            let product = self.0[i] as u128 * factor as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        (carry == 0).then_some(U256(out))
+    }
+
+    /// Divides by a non-zero `u64`, returning the quotient and remainder.
+    pub fn div_rem_u64(self, divisor: u64) -> (U256, u64) {
+        assert!(divisor != 0, "division by zero");
+        let mut out = [0u64; 4];
+        let mut rem = 0u128;
+        for i in (0..4).rev() {
+            let current = (rem << 64) | self.0[i] as u128;
+            out[i] = (current / divisor as u128) as u64;
+            rem = current % divisor as u128;
+        }
+        (U256(out), rem as u64)
+    }
+
+    /// Divides by a non-zero value, returning `None` when `divisor` is zero.
+    pub fn checked_div(self, divisor: U256) -> Option<U256> {
+        if divisor.is_zero() {
+            return None;
+        }
+        // Schoolbook binary long division.
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for bit in (0..self.bits()).rev() {
+            remainder = remainder << 1;
+            if self.bit(bit) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= divisor {
+                remainder = remainder - divisor;
+                quotient.0[bit as usize / 64] |= 1 << (bit % 64);
+            }
+        }
+        Some(quotient)
+    }
+
+    fn bit(self, index: u32) -> bool {
+        self.0[index as usize / 64] >> (index % 64) & 1 == 1
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        self.checked_add(other).expect("U256 addition overflowed")
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, other: U256) -> U256 {
+        self.checked_sub(other).expect("U256 subtraction underflowed")
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    fn div(self, other: U256) -> U256 {
+        self.checked_div(other).expect("U256 division by zero")
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    /// Shifts left, discarding bits shifted past the top.
+    fn shl(self, shift: u32) -> U256 {
+        if shift >= 256 {
+            return U256::ZERO;
+        }
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        let mut out = [0u64; 4];
+        for (i, limb) in out.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(out)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        if shift >= 256 {
+            return U256::ZERO;
+        }
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        let mut out = [0u64; 4];
+        for (i, limb) in out.iter_mut().take(4 - limbs).enumerate() {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(out)
+    }
+}
+
+impl fmt::Display for U256 {
+    /// Formats the value in decimal.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut chunks = Vec::new();
+        let mut value = *self;
+        loop {
+            let (quotient, rem) = value.div_rem_u64(CHUNK);
+            chunks.push(rem);
+            value = quotient;
+            if value.is_zero() {
+                break;
+            }
+        }
+        let mut out = chunks.pop().unwrap().to_string();
+        for chunk in chunks.iter().rev() {
+            out.push_str(&format!("{:019}", chunk));
+        }
+        f.pad_integral(true, "", &out)
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        for byte in self.to_be_bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_carries_across_limbs() {
+        let low = U256::from(u64::MAX);
+        assert_eq!(low + U256::ONE, U256([0, 1, 0, 0]));
+        assert_eq!(U256([0, 1, 0, 0]) - U256::ONE, low);
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(U256::ZERO.checked_sub(U256::ONE), None);
+
+        // (2^64 - 1)^2 = 2^128 - 2^65 + 1
+        let square = low.checked_mul_u64(u64::MAX).unwrap();
+        assert_eq!(square, U256([1, u64::MAX - 1, 0, 0]));
+        assert_eq!(U256::MAX.checked_mul_u64(2), None);
+        assert_eq!(square / low, low);
+        assert_eq!((U256::ONE << 200) / (U256::ONE << 100), U256::ONE << 100);
+        assert_eq!(U256::from(1000).div_rem_u64(7), (U256::from(142), 6));
+        assert_eq!(U256::ONE.checked_div(U256::ZERO), None);
+    }
+
+    #[test]
+    fn shifts_move_bits_between_limbs() {
+        let value = U256::from(0x8000_0000_0000_0001);
+        assert_eq!(value << 1, U256([2, 1, 0, 0]));
+        assert_eq!(U256([2, 1, 0, 0]) >> 1, value);
+        assert_eq!(U256::ONE << 255 >> 255, U256::ONE);
+        assert_eq!(U256::ONE << 255 << 1, U256::ZERO);
+        assert_eq!(U256::MAX >> 256, U256::ZERO);
+        assert_eq!((U256::ONE << 130).bits(), 131);
+    }
+
+    #[test]
+    fn bytes_and_ordering_are_big_endian() {
+        let bytes = ((U256::ONE << 255) + U256::ONE).to_be_bytes();
+        assert_eq!((bytes[0], bytes[31]), (0x80, 0x01));
+        assert_eq!(U256::from_be_bytes(bytes), (U256::ONE << 255) + U256::ONE);
+        assert!(U256::ONE << 64 > U256::from(u64::MAX));
+        assert_eq!(
+            U256::MAX.to_string(),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+    }
+}
@@ -0,0 +1,357 @@
+// Unspent transaction outputs and the rules for spending them
+
+use crate::crypto::sha256::sha256d;
+use crate::error::ChainError;
+use crate::hash::Hash256;
+use crate::smt::SparseMerkleTree;
+use crate::transaction::{Address, OutPoint, Transaction, TxOutput};
+use std::collections::{HashMap, HashSet};
+
+/// An unspent output and where it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utxo {
+    pub output: TxOutput,
+    /// Height of the block that created the output.
+    pub height: u32,
+    /// Whether a coinbase created the output.
+    pub coinbase: bool,
+}
+
+impl Utxo {
+    /// First height at which the output may be spent: coinbase outputs after
+    /// genesis wait `coinbase_maturity` blocks, everything else is spendable at once.
+    pub fn spendable_from(&self, coinbase_maturity: u32) -> u32 {
+        if self.coinbase && self.height > 0 {
+            self.height.saturating_add(coinbase_maturity)
+        } else {
+            self.height
+        }
+    }
+}
+
+/// Every output that has been created and not yet spent, keyed by where it was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoSet {
+    outputs: HashMap<OutPoint, Utxo>,
+    /// Commits to `outputs`, kept up to date as outputs are created and spent.
+    tree: SparseMerkleTree,
+    coinbase_maturity: u32,
+}
+
+/// What connecting a block changed, so that `UtxoSet::disconnect` can revert it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockUndo {
+    spent: Vec<(OutPoint, Utxo)>,
+    created: Vec<OutPoint>,
+}
+
+impl UtxoSet {
+    /// Creates an empty set whose coinbase outputs mature after `coinbase_maturity` blocks.
+    pub fn new(coinbase_maturity: u32) -> Self {
+        UtxoSet {
+            outputs: HashMap::new(),
+            tree: SparseMerkleTree::new(),
+            coinbase_maturity,
+        }
+    }
+
+    /// Returns the unspent output at `outpoint`, if any.
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&Utxo> {
+        self.outputs.get(outpoint)
+    }
+
+    /// Number of unspent outputs.
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Returns `true` if there are no unspent outputs.
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Unspent outputs paying `address`, mature or not, ordered by outpoint.
+    pub fn unspent_for(&self, address: &Address) -> Vec<(OutPoint, Utxo)> {
+        let mut unspent: Vec<(OutPoint, Utxo)> = self
+            .outputs
+            .iter()
+            .filter(|(_, utxo)| utxo.output.recipient == *address)
+            .map(|(outpoint, utxo)| (*outpoint, *utxo))
+            .collect();
+        unspent.sort_by_key(|(outpoint, _)| *outpoint);
+        unspent
+    }
+
+    /// Sum of the outputs paying `address` that a block at `height` may spend,
+    /// saturating at `u64::MAX`.
+    pub fn balance(&self, address: &Address, height: u32) -> u64 {
+        self.outputs
+            .values()
+            .filter(|utxo| utxo.output.recipient == *address && utxo.spendable_from(self.coinbase_maturity) <= height)
+            .fold(0u64, |total, utxo| total.saturating_add(utxo.output.amount))
+    }
+
+    /// Commits to every unspent output: the root of a sparse Merkle tree keyed by
+    /// the hash of each outpoint, over the hashes of the outputs' encodings.
+    pub fn state_root(&self) -> Hash256 {
+        self.tree.root()
+    }
+
+    /// Applies the transactions of the block at `height` in order, each spending
+    /// outputs that are unspent after the ones before it.
+    ///
+    /// Every transaction but a coinbase must pass `check_spend`. A coinbase only
+    /// creates outputs; where it may appear and how much it may create is checked
+    /// by `Block::validate_coinbase`.
+    /// On error the set is left unchanged.
+    pub fn connect(&mut self, height: u32, transactions: &[Transaction]) -> Result<BlockUndo, ChainError> {
+        let mut undo = BlockUndo::default();
+        for transaction in transactions {
+            if let Err(err) = self.apply(height, transaction, &mut undo) {
+                self.disconnect(undo);
+                return Err(err);
+            }
+        }
+        Ok(undo)
+    }
+
+    /// Reverts a block applied by `connect`, restoring the outputs it spent.
+    pub fn disconnect(&mut self, undo: BlockUndo) {
+        for outpoint in undo.created.iter().rev() {
+            self.remove(outpoint);
+        }
+        for (outpoint, utxo) in undo.spent {
+            self.insert(outpoint, utxo);
+        }
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, utxo: Utxo) {
+        let mut bytes = [0u8; 81];
+        bytes[0..36].copy_from_slice(&outpoint_bytes(&outpoint));
+        bytes[36..68].copy_from_slice(&utxo.output.recipient.0);
+        bytes[68..76].copy_from_slice(&utxo.output.amount.to_le_bytes());
+        bytes[76..80].copy_from_slice(&utxo.height.to_le_bytes());
+        bytes[80] = utxo.coinbase as u8;
+        self.tree.insert(Hash256(sha256d(&outpoint_bytes(&outpoint))), Hash256(sha256d(&bytes)));
+        self.outputs.insert(outpoint, utxo);
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) -> Option<Utxo> {
+        let utxo = self.outputs.remove(outpoint)?;
+        self.tree.remove(&Hash256(sha256d(&outpoint_bytes(outpoint))));
+        Some(utxo)
+    }
+
+    /// Checks that `transaction`, which must not be a coinbase, may spend its
+    /// inputs in a block at `height`, with `lookup` telling what each input is.
+    ///
+    /// It must spend at least one output and no output twice, every input must be
+    /// unspent, mature at `height` and paid to the sender, and the inputs must add
+    /// up exactly to the outputs plus the fee.
+    pub fn check_spend(
+        &self,
+        transaction: &Transaction,
+        height: u32,
+        lookup: impl Fn(&OutPoint) -> Option<Utxo>,
+    ) -> Result<(), ChainError> {
+        let tx_id = transaction.id();
+        if transaction.inputs.is_empty() {
+            return Err(ChainError::NoInputs(tx_id));
+        }
+        let mut seen = HashSet::new();
+        let mut input_total = 0u128;
+        for input in &transaction.inputs {
+            let utxo = seen
+                .insert(*input)
+                .then(|| lookup(input))
+                .flatten()
+                .ok_or(ChainError::MissingInput { tx_id, input: *input })?;
+            if utxo.output.recipient != transaction.sender {
+                return Err(ChainError::InputNotOwned { tx_id, input: *input });
+            }
+            let spendable_from = utxo.spendable_from(self.coinbase_maturity);
+            if height < spendable_from {
+                return Err(ChainError::ImmatureCoinbase {
+                    tx_id,
+                    input: *input,
+                    spendable_from,
+                });
+            }
+            input_total += utxo.output.amount as u128;
+        }
+        let output_total = transaction
+            .outputs
+            .iter()
+            .fold(transaction.fee as u128, |total, output| total + output.amount as u128);
+        if input_total != output_total {
+            return Err(ChainError::UnbalancedTransaction {
+                tx_id,
+                input_total,
+                output_total,
+            });
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, height: u32, transaction: &Transaction, undo: &mut BlockUndo) -> Result<(), ChainError> {
+        let tx_id = transaction.id();
+        let coinbase = transaction.is_coinbase();
+        if !coinbase {
+            self.check_spend(transaction, height, |input| self.outputs.get(input).copied())?;
+            for input in &transaction.inputs {
+                if let Some(utxo) = self.remove(input) {
+                    undo.spent.push((*input, utxo));
+                }
+            }
+        }
+        for (index, output) in transaction.outputs.iter().enumerate() {
+            let outpoint = OutPoint {
+                tx_id,
+                index: index as u32,
+            };
+            let utxo = Utxo {
+                output: *output,
+                height,
+                coinbase,
+            };
+            self.insert(outpoint, utxo);
+            undo.created.push(outpoint);
+        }
+        Ok(())
+    }
+}
+
+/// An outpoint's encoding: the transaction id followed by the output index.
+fn outpoint_bytes(outpoint: &OutPoint) -> [u8; 36] {
+    let mut bytes = [0u8; 36];
+    bytes[0..32].copy_from_slice(outpoint.tx_id.as_bytes());
+    bytes[32..36].copy_from_slice(&outpoint.index.to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use std::slice;
+
+    #[test]
+    fn coinbase_outputs_wait_for_maturity() {
+        let miner = SigningKey::from_seed(&[1; 32]);
+        let to_miner = |amount| TxOutput {
+            recipient: Address::of(&miner),
+            amount,
+        };
+        let mut utxos = UtxoSet::new(2);
+        let genesis = Transaction::coinbase(0, vec![to_miner(10)]);
+        let reward = Transaction::coinbase(1, vec![to_miner(50)]);
+        utxos.connect(0, slice::from_ref(&genesis)).unwrap();
+        utxos.connect(1, slice::from_ref(&reward)).unwrap();
+        assert_eq!(utxos.balance(&Address::of(&miner), 2), 10);
+        assert_eq!(utxos.balance(&Address::of(&miner), 3), 60);
+
+        // Genesis allocations are spendable at once, block rewards only two blocks on.
+        let input = |transaction: &Transaction| OutPoint {
+            tx_id: transaction.id(),
+            index: 0,
+        };
+        let spend = Transaction::new_signed(&miner, vec![input(&genesis), input(&reward)], vec![to_miner(59)], 1, 0);
+        let before = utxos.clone();
+        assert_eq!(
+            utxos.connect(2, slice::from_ref(&spend)),
+            Err(ChainError::ImmatureCoinbase {
+                tx_id: spend.id(),
+                input: input(&reward),
+                spendable_from: 3,
+            })
+        );
+        assert_eq!(utxos, before);
+        let undo = utxos.connect(3, slice::from_ref(&spend)).unwrap();
+        assert_eq!(utxos.len(), 1);
+        utxos.disconnect(undo);
+        assert_eq!(utxos, before);
+        assert_eq!(utxos.state_root(), before.state_root());
+    }
+
+    #[test]
+    fn rejects_double_spends_missing_outputs_and_unbalanced_spends() {
+        let (alice, bob) = (SigningKey::from_seed(&[1; 32]), SigningKey::from_seed(&[2; 32]));
+        let pay = |key: &SigningKey, amount| TxOutput {
+            recipient: Address::of(key),
+            amount,
+        };
+        let mut utxos = UtxoSet::new(0);
+        let genesis = Transaction::coinbase(0, vec![pay(&alice, 100), pay(&bob, 50)]);
+        utxos.connect(0, slice::from_ref(&genesis)).unwrap();
+        let coin = OutPoint {
+            tx_id: genesis.id(),
+            index: 0,
+        };
+        let before = utxos.clone();
+
+        // Twice within one block: the second spend finds the output gone and the
+        // first is rolled back with it.
+        let to_bob = Transaction::new_signed(&alice, vec![coin], vec![pay(&bob, 99)], 1, 0);
+        let to_alice = Transaction::new_signed(&alice, vec![coin], vec![pay(&alice, 98)], 2, 0);
+        assert_eq!(
+            utxos.connect(1, &[to_bob.clone(), to_alice.clone()]),
+            Err(ChainError::MissingInput {
+                tx_id: to_alice.id(),
+                input: coin,
+            })
+        );
+        assert_eq!(utxos, before);
+        // Twice within one transaction.
+        let twice = Transaction::new_signed(&alice, vec![coin, coin], vec![pay(&bob, 199)], 1, 0);
+        assert_eq!(
+            utxos.connect(1, slice::from_ref(&twice)),
+            Err(ChainError::MissingInput {
+                tx_id: twice.id(),
+                input: coin,
+            })
+        );
+
+        // Twice across blocks.
+        utxos.connect(1, slice::from_ref(&to_bob)).unwrap();
+        assert_eq!(
+            utxos.connect(2, slice::from_ref(&to_alice)),
+            Err(ChainError::MissingInput {
+                tx_id: to_alice.id(),
+                input: coin,
+            })
+        );
+
+        // An output that never existed.
+        let made_up = OutPoint {
+            tx_id: genesis.id(),
+            index: 2,
+        };
+        let from_nothing = Transaction::new_signed(&bob, vec![made_up], vec![pay(&alice, 10)], 0, 0);
+        assert_eq!(
+            utxos.connect(2, slice::from_ref(&from_nothing)),
+            Err(ChainError::MissingInput {
+                tx_id: from_nothing.id(),
+                input: made_up,
+            })
+        );
+
+        // Paying out more than the inputs, and less.
+        let bobs = OutPoint {
+            tx_id: genesis.id(),
+            index: 1,
+        };
+        for (amount, fee) in [(50, 1), (40, 5)] {
+            let unbalanced = Transaction::new_signed(&bob, vec![bobs], vec![pay(&alice, amount)], fee, 0);
+            assert_eq!(
+                utxos.connect(2, slice::from_ref(&unbalanced)),
+                Err(ChainError::UnbalancedTransaction {
+                    tx_id: unbalanced.id(),
+                    input_total: 50,
+                    output_total: (amount + fee) as u128,
+                })
+            );
+        }
+        let balanced = Transaction::new_signed(&bob, vec![bobs], vec![pay(&alice, 45)], 5, 0);
+        utxos.connect(2, slice::from_ref(&balanced)).unwrap();
+    }
+}
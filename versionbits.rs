@@ -0,0 +1,256 @@
+// Soft-fork deployments signalled by miners through header version bits (BIP9)
+
+use crate::block::{self, BlockHeader};
+use crate::params::ChainParams;
+use std::fmt;
+
+/// The top three version bits of a header that signals deployments: `001`.
+pub const TOP_BITS: u32 = 0x2000_0000;
+
+/// Mask selecting the top three version bits.
+pub const TOP_MASK: u32 = 0xe000_0000;
+
+/// Deployments may use version bits 0 to 28, below `TOP_MASK`.
+pub const MAX_BIT: u8 = 28;
+
+/// A rule change miners signal readiness for by setting `bit` in the header version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deployment {
+    pub name: &'static str,
+    /// Version bit signalling for the deployment, at most `MAX_BIT`.
+    pub bit: u8,
+    /// Median time past, in seconds since the Unix epoch, from which signalling counts.
+    pub start_time: u64,
+    /// Median time past after which the deployment fails unless it locked in.
+    pub timeout: u64,
+}
+
+impl Deployment {
+    /// The version bit as a mask.
+    pub fn mask(&self) -> u32 {
+        1 << self.bit
+    }
+
+    /// Returns `true` if `header` signals for the deployment: its top version
+    /// bits are `TOP_BITS` and the deployment's bit is set.
+    pub fn is_signalled_by(&self, header: &BlockHeader) -> bool {
+        header.version & TOP_MASK == TOP_BITS && header.version & self.mask() != 0
+    }
+}
+
+/// Where a deployment stands. It is the same for every block of a signalling
+/// window and only changes at window boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeploymentState {
+    /// The start time has not been reached.
+    Defined,
+    /// Blocks signal for the deployment, waiting for the threshold.
+    Started,
+    /// The threshold was reached; the deployment activates after one more window.
+    LockedIn,
+    /// The deployment's rules apply.
+    Active,
+    /// The timeout passed before the threshold was reached.
+    Failed,
+}
+
+impl fmt::Display for DeploymentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeploymentState::Defined => "DEFINED",
+            DeploymentState::Started => "STARTED",
+            DeploymentState::LockedIn => "LOCKED_IN",
+            DeploymentState::Active => "ACTIVE",
+            DeploymentState::Failed => "FAILED",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Computes the state of `deployment` for the block following `chain`, every
+/// block or header up to and including its parent, starting with genesis.
+///
+/// Windows are `params.signal_window` blocks long and start at multiples of it;
+/// the first one is always `Defined`. At each boundary the state moves on from
+/// the previous window's, judged by the median time past of the window's last
+/// block and how many of its blocks signalled:
+///
+/// - `Defined` becomes `Failed` at the timeout, or `Started` at the start time;
+/// - `Started` becomes `Failed` at the timeout, or `LockedIn` once at least
+///   `params.signal_threshold` blocks of the window signalled;
+/// - `LockedIn` becomes `Active`; `Active` and `Failed` are final.
+///
+/// This replays every window from genesis; a chain that grows keeps its states
+/// in `DeploymentStates` instead.
+pub fn state<H: AsRef<BlockHeader>>(params: &ChainParams, deployment: &Deployment, chain: &[H]) -> DeploymentState {
+    let window = params.signal_window.max(1) as usize;
+    (window..=chain.len())
+        .step_by(window)
+        .fold(DeploymentState::Defined, |state, end| next_state(params, deployment, state, &chain[..end]))
+}
+
+/// The state of `deployment` in the window after the one `chain` ends with, given
+/// its `state` in that window; see `state`.
+fn next_state<H: AsRef<BlockHeader>>(
+    params: &ChainParams,
+    deployment: &Deployment,
+    state: DeploymentState,
+    chain: &[H],
+) -> DeploymentState {
+    let time = block::median_time_past(chain);
+    match state {
+        DeploymentState::Defined if time >= deployment.timeout => DeploymentState::Failed,
+        DeploymentState::Defined if time >= deployment.start_time => DeploymentState::Started,
+        DeploymentState::Started if time >= deployment.timeout => DeploymentState::Failed,
+        DeploymentState::Started => {
+            let window = params.signal_window.max(1) as usize;
+            let signals = chain[chain.len() - window..]
+                .iter()
+                .filter(|header| deployment.is_signalled_by(header.as_ref()))
+                .count();
+            if signals >= params.signal_threshold as usize {
+                DeploymentState::LockedIn
+            } else {
+                DeploymentState::Started
+            }
+        }
+        DeploymentState::LockedIn => DeploymentState::Active,
+        DeploymentState::Defined | DeploymentState::Active | DeploymentState::Failed => state,
+    }
+}
+
+/// The state of every deployment of a chain at each window boundary it has
+/// passed, so that each window is judged once rather than on every query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeploymentStates {
+    /// `windows[i][d]` is the state of `params.deployments[d]` in window `i + 1`.
+    windows: Vec<Vec<DeploymentState>>,
+}
+
+impl DeploymentStates {
+    /// Judges the windows `chain` completed since the last call. `chain` must be
+    /// the chain of the earlier calls, grown or cut short.
+    pub fn update<H: AsRef<BlockHeader>>(&mut self, params: &ChainParams, chain: &[H]) {
+        let window = params.signal_window.max(1) as usize;
+        self.windows.truncate(chain.len() / window);
+        for end in ((self.windows.len() + 1) * window..=chain.len()).step_by(window) {
+            let states = params
+                .deployments
+                .iter()
+                .enumerate()
+                .map(|(index, deployment)| next_state(params, deployment, self.get(index), &chain[..end]))
+                .collect();
+            self.windows.push(states);
+        }
+    }
+
+    /// The state of `params.deployments[index]` for the block following the chain
+    /// last passed to `update`.
+    pub fn get(&self, index: usize) -> DeploymentState {
+        self.windows.last().map_or(DeploymentState::Defined, |states| states[index])
+    }
+
+    /// The version the block following that chain is mined with: `TOP_BITS` plus
+    /// the bit of every deployment that is `Started` or `LockedIn`. Signalling has
+    /// no effect once locked in, but keeps showing the upgrade being adopted.
+    pub fn next_version(&self, params: &ChainParams) -> u32 {
+        params
+            .deployments
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| matches!(self.get(*index), DeploymentState::Started | DeploymentState::LockedIn))
+            .fold(TOP_BITS, |version, (_, deployment)| version | deployment.mask())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::pow::PowKind;
+
+    /// Four-block windows locking in at three signals, with a deployment on bit 1.
+    fn params(start_time: u64, timeout: u64) -> ChainParams {
+        let mut params = ChainParams::new(PowKind::Sha256d, 0x207f_ffff);
+        params.signal_window = 4;
+        params.signal_threshold = 3;
+        params.deployments = vec![Deployment {
+            name: "test",
+            bit: 1,
+            start_time,
+            timeout,
+        }];
+        params
+    }
+
+    /// A chain whose block `h` is stamped `10 * h` and versioned `versions[h]`, so
+    /// that from 11 blocks on the median time past of `h` blocks is `10 * (h - 6)`.
+    fn chain(params: &ChainParams, versions: &[u32]) -> Vec<BlockHeader> {
+        let genesis = Block::genesis(params).header;
+        versions
+            .iter()
+            .enumerate()
+            .map(|(height, &version)| BlockHeader {
+                version,
+                height: height as u32,
+                timestamp: 10 * height as u64,
+                ..genesis
+            })
+            .collect()
+    }
+
+    /// The state at every window boundary of `chain`, checking that the cached
+    /// states agree with replaying from genesis at every length.
+    fn states(params: &ChainParams, chain: &[BlockHeader]) -> Vec<DeploymentState> {
+        let mut cached = DeploymentStates::default();
+        let mut states = Vec::new();
+        for len in 1..=chain.len() {
+            cached.update(params, &chain[..len]);
+            assert_eq!(cached.get(0), state(params, &params.deployments[0], &chain[..len]), "length {}", len);
+            if len % 4 == 0 {
+                states.push(cached.get(0));
+            }
+        }
+        states
+    }
+
+    #[test]
+    fn deployments_start_lock_in_at_the_threshold_and_activate() {
+        use DeploymentState::*;
+        let params = params(40, 1_000);
+        let signal = TOP_BITS | params.deployments[0].mask();
+        let mut versions = vec![TOP_BITS; 24];
+        // Two signals and one with the wrong top bits fall one short of the threshold.
+        versions[8..12].copy_from_slice(&[signal, signal, params.deployments[0].mask(), TOP_BITS]);
+        versions[12..16].copy_from_slice(&[signal, TOP_BITS, signal, signal]);
+        let chain = chain(&params, &versions);
+        // The median time past of 8 blocks is 40, the start time.
+        assert_eq!(states(&params, &chain), [Defined, Started, Started, LockedIn, Active, Active]);
+
+        let mut cached = DeploymentStates::default();
+        for (len, version) in [(4, TOP_BITS), (8, signal), (16, signal), (20, TOP_BITS)] {
+            cached.update(&params, &chain[..len]);
+            assert_eq!(cached.next_version(&params), version, "length {}", len);
+        }
+        // A chain cut short is judged again from where it ends.
+        cached.update(&params, &chain[..13]);
+        assert_eq!(cached.get(0), Started);
+    }
+
+    #[test]
+    fn deployments_fail_at_the_timeout() {
+        use DeploymentState::*;
+        // The median time past reaches the timeout at the boundary after 16 blocks,
+        // which fails the deployment even though that window signalled throughout.
+        let params = params(40, 100);
+        let signal = TOP_BITS | params.deployments[0].mask();
+        let mut versions = vec![TOP_BITS; 24];
+        versions[12..].fill(signal);
+        let chain = chain(&params, &versions);
+        assert_eq!(states(&params, &chain), [Defined, Started, Started, Failed, Failed, Failed]);
+
+        // One that times out before it starts never does.
+        let params = self::params(1_000, 60);
+        assert_eq!(states(&params, &chain), [Defined, Defined, Failed, Failed, Failed, Failed]);
+    }
+}